@@ -0,0 +1,181 @@
+use crate::{
+    configs::LlmConfig,
+    providers::{self, GenerateOptions},
+};
+use clap::Subcommand;
+use reqwest::Url;
+use std::{net::IpAddr, time::Duration};
+use toml::{Table, Value};
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the config file with API keys and other secrets redacted
+    Show,
+    /// Set a key of a provider's table or of a profile, e.g. `ollama.MODEL`
+    /// or `work.TEMPERATURE`; `<profile>.provider` creates a profile
+    Set { key: String, value: String },
+    /// Check every configured provider and profile, and that local servers
+    /// are reachable
+    Validate,
+    /// Print where the config file is
+    Path,
+}
+
+impl ConfigCommand {
+    pub async fn run(self) -> Result<(), String> {
+        match self {
+            ConfigCommand::Show => show(),
+            ConfigCommand::Set { key, value } => set(&key, value),
+            ConfigCommand::Validate => validate().await,
+            ConfigCommand::Path => {
+                println!("{}", LlmConfig::path());
+                Ok(())
+            }
+        }
+    }
+}
+
+fn show() -> Result<(), String> {
+    let mut llm_config = LlmConfig::load()?;
+    for provider in providers::PROVIDERS {
+        let mut section = llm_config.get_configs(provider.name)?;
+        redact(&mut section, provider.name);
+        llm_config.set_configs(provider.name, section)?;
+    }
+    for profile in llm_config.profiles.values_mut() {
+        redact(&mut profile.settings, &profile.provider);
+    }
+    let contents = toml::to_string(&llm_config).map_err(|error| error.to_string())?;
+    println!("# {}\n{contents}", LlmConfig::path());
+    Ok(())
+}
+
+/// Hides the values of `provider`'s secrets; where they are read from stays
+/// visible.
+fn redact(section: &mut Table, provider: &str) {
+    let secrets = providers::get_provider(provider).map_or(&[][..], |provider| provider.secrets);
+    for secret in secrets {
+        if let Some(value) = section.get_mut(*secret) {
+            *value = Value::from(REDACTED);
+        }
+    }
+}
+
+fn set(key: &str, value: String) -> Result<(), String> {
+    let path = key.strip_prefix("profiles.").unwrap_or(key);
+    let Some((table, key)) = path.split_once('.') else {
+        return Err(format!(
+            "Expected <provider>.<KEY> or <profile>.<KEY>, got {key}"
+        ));
+    };
+    if key == "provider" {
+        return set_profile_provider(table, value);
+    }
+    // settings keep numbers as strings, as older versions did, which every
+    // provider accepts
+    let settings = Table::from_iter([(key.to_uppercase(), Value::String(value))]);
+    if providers::get_provider(table).is_some() {
+        return GenerateOptions::default().store(table, settings);
+    }
+    let llm_config = LlmConfig::load()?;
+    let Some(profile) = llm_config.profiles.get(table) else {
+        return Err(format!(
+            "No provider or profile named {table}. \
+            Create the profile with `llm-lsp config set {table}.provider <provider>`"
+        ));
+    };
+    let options = GenerateOptions {
+        profile: Some(table.to_owned()),
+        ..GenerateOptions::default()
+    };
+    options.store(&profile.provider, settings)
+}
+
+fn set_profile_provider(name: &str, provider: String) -> Result<(), String> {
+    if providers::get_provider(&provider).is_none() {
+        return Err(format!(
+            "Invalid provider: {provider}. To check valid providers run `llm-lsp list-providers`"
+        ));
+    }
+    let mut llm_config = LlmConfig::load()?;
+    llm_config
+        .profiles
+        .entry(name.to_owned())
+        .or_default()
+        .provider = provider;
+    llm_config.store()
+}
+
+/// Prints the state of every provider table that differs from the defaults
+/// and of every profile, failing if any of them is invalid.
+async fn validate() -> Result<(), String> {
+    let llm_config = LlmConfig::load()?;
+    let defaults = LlmConfig::default();
+    let mut sections = vec![];
+    for provider in providers::PROVIDERS {
+        let section = llm_config.get_configs(provider.name)?;
+        if section != defaults.get_configs(provider.name)? {
+            sections.push((format!("[{}]", provider.name), provider.name, section));
+        }
+    }
+    for (name, profile) in &llm_config.profiles {
+        let (_, section) = llm_config.get_profile(name)?;
+        sections.push((format!("[profiles.{name}]"), &profile.provider, section));
+    }
+    if sections.is_empty() {
+        return Err("No provider is configured, run `llm-lsp generate-config`".to_owned());
+    }
+
+    let mut failures = 0;
+    for (table, provider, section) in sections {
+        let result = match providers::get_provider(provider) {
+            Some(llm_provider) => match llm_provider.validate(section.clone()) {
+                Ok(()) => check_local_urls(&section).await,
+                Err(error) => Err(error),
+            },
+            None => Err(format!("Invalid provider: {provider}")),
+        };
+        match result {
+            Ok(()) => println!("{table}: ok"),
+            Err(error) => {
+                failures += 1;
+                println!("{table}: {error}");
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{failures} invalid in {}", LlmConfig::path()));
+    }
+    Ok(())
+}
+
+/// Checks that servers the section points at on this machine, such as a
+/// local ollama, answer at all.
+async fn check_local_urls(section: &Table) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(3))
+        .build()
+        .map_err(|error| error.to_string())?;
+    for (key, value) in section {
+        let Some(url) = value.as_str().filter(|_| key.ends_with("_URL")) else {
+            continue;
+        };
+        let Ok(parsed) = Url::parse(url) else {
+            continue;
+        };
+        let host = parsed.host_str().unwrap_or_default();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let local = host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        if local {
+            // any response will do, the endpoint may not accept GET
+            client
+                .get(parsed)
+                .send()
+                .await
+                .map_err(|error| format!("{key} {url} is not reachable: {error}"))?;
+        }
+    }
+    Ok(())
+}
@@ -1,9 +1,36 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::{
+    providers::{
+        self, codeium::CodeiumConfig, ollama::OllamaConfig, openai::OpenAiConfig, Provider,
+    },
+    secrets,
+};
+use confy::ConfyError;
+use reqwest::Url;
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    str::FromStr,
+    time::Duration,
+};
+use toml::Table;
+
+/// Instruction sent with every command so the reply can be applied as-is.
+pub const COMMAND_SYSTEM_PROMPT: &str = "You are a coding assistant embedded in a text editor. \
+The user sends a code selection and an instruction. \
+Reply with only the code asked for, which replaces the selection unless the instruction says otherwise, \
+without explanations and without markdown code fences.";
+
+/// Command attached to completions that carry a provider id, run by the
+/// client when the completion is accepted.
+pub const ACCEPT_COMPLETION_COMMAND: &str = "accept_completion";
 
 pub struct LspConfig<'a> {
     pub commands: Vec<Command<'a>>,
     pub trigger_characters: Vec<&'a str>,
+    /// How long a completion request waits for the user to stop typing
+    /// before it is sent to the provider.
+    pub completion_debounce: Duration,
 }
 
 impl<'a> LspConfig<'a> {
@@ -11,6 +38,7 @@ impl<'a> LspConfig<'a> {
         LspConfig {
             commands: Self::get_commands(),
             trigger_characters: Self::get_trigger_characters(),
+            completion_debounce: Self::get_completion_debounce(),
         }
     }
 
@@ -20,26 +48,32 @@ impl<'a> LspConfig<'a> {
                 key: "resolve_diagnostics",
                 label: "Resolve diagnostics",
                 query: "Resolve the diagnostics for this code.",
+                output: CommandOutput::ReplaceSelection,
             },
             Command {
                 key: "generate_docs",
                 label: "Generate documentation",
                 query: "Add documentation to this code.",
+                output: CommandOutput::ReplaceSelection,
             },
             Command {
                 key: "improve_code",
                 label: "Improve code",
                 query: "Improve this code.",
+                output: CommandOutput::ReplaceSelection,
             },
             Command {
                 key: "refactor_from_comment",
                 label: "Refactor code from a comment",
                 query: "Refactor this code based on the comment.",
+                output: CommandOutput::ReplaceSelection,
             },
             Command {
                 key: "write_test",
                 label: "Write a unit test",
-                query: "Write a unit test for this code. Do not include any imports.",
+                query: "Write a unit test for this code, to be inserted after it. \
+                Do not include any imports.",
+                output: CommandOutput::InsertAfterSelection,
             },
         ]
     }
@@ -47,43 +81,204 @@ impl<'a> LspConfig<'a> {
     fn get_trigger_characters() -> Vec<&'a str> {
         vec!["{", "(", " "]
     }
+
+    fn get_completion_debounce() -> Duration {
+        Duration::from_millis(250)
+    }
 }
 
 pub struct Command<'a> {
     pub key: &'a str,
-    #[allow(unused)]
-    label: &'a str,
-    #[allow(unused)]
-    query: &'a str,
+    pub label: &'a str,
+    pub query: &'a str,
+    pub output: CommandOutput,
+}
+
+/// Where the answer to a command goes.
+#[derive(Clone, Copy)]
+pub enum CommandOutput {
+    ReplaceSelection,
+    /// For new code such as tests, which must not replace the code they are about.
+    InsertAfterSelection,
 }
 
 #[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LlmConfig {
-    codeium: HashMap<String, String>,
-    ollama: HashMap<String, String>,
-    openapi: HashMap<String, String>,
+    pub codeium: CodeiumConfig,
+    pub ollama: OllamaConfig,
+    #[serde(alias = "openapi")]
+    pub openai: OpenAiConfig,
     copilot: HashMap<String, String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named set of settings selected with `llm-lsp server --profile <name>`,
+/// e.g. `[profiles.local-ollama]`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub provider: String,
+    /// Settings layered over the provider's own table, such as `MODEL`.
+    #[serde(flatten)]
+    pub settings: Table,
 }
 
 impl LlmConfig {
-    pub fn generate_config(
-        provider: String,
-        config_map: HashMap<String, String>,
-    ) -> Result<(), String> {
-        let mut llm_config: LlmConfig = confy::load("llm-lsp", None).unwrap();
-        match provider.as_str() {
-            "codeium" => llm_config.codeium.extend(config_map),
-            _ => return Err("Provider {provider} is not supported as of now!".to_owned()),
-        };
-        confy::store("llm-lsp", None, llm_config).unwrap();
-        Ok(())
+    /// Loads the config file, creating it with defaults if it does not exist.
+    pub fn load() -> Result<Self, String> {
+        confy::load("llm-lsp", None).map_err(|error| match error {
+            // the TOML error names the offending key and line
+            ConfyError::BadTomlData(error) => format!("Invalid {}: {error}", Self::path()),
+            error => format!("Could not load {}: {error}", Self::path()),
+        })
+    }
+
+    pub fn store(self) -> Result<(), String> {
+        confy::store("llm-lsp", None, self)
+            .map_err(|error| format!("Could not save {}: {error}", Self::path()))
+    }
+
+    pub fn path() -> String {
+        confy::get_configuration_file_path("llm-lsp", None)
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|_| "the llm-lsp config file".to_owned())
+    }
+
+    /// Returns the settings of `provider`'s table.
+    pub fn get_configs(&self, provider: &str) -> Result<Table, String> {
+        Self::provider(provider)?
+            .table(self)
+            .map_err(|error| format!("Invalid [{provider}] config: {error}"))
+    }
+
+    /// Replaces `provider`'s table with `section`.
+    pub fn set_configs(&mut self, provider: &str, section: Table) -> Result<(), String> {
+        Self::provider(provider)?
+            .set_table(self, section)
+            .map_err(|error| format!("Invalid [{provider}] config: {error}"))
+    }
+
+    fn provider(name: &str) -> Result<&'static Provider, String> {
+        providers::get_provider(name)
+            .ok_or_else(|| format!("Provider {name} is not supported as of now!"))
+    }
+
+    /// Returns the provider of profile `name` and its settings, on top of
+    /// whatever the provider's table holds, e.g. the API key.
+    pub fn get_profile(&self, name: &str) -> Result<(String, Table), String> {
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            format!(
+                "Profile {name} not found. Define it as [profiles.{name}] in {}",
+                Self::path()
+            )
+        })?;
+        let mut settings = self.get_configs(&profile.provider)?;
+        secrets::extend(&mut settings, profile.settings.clone());
+        Ok((profile.provider.clone(), settings))
     }
+}
+
+/// Deserializes a setting written either as a native TOML value or, as
+/// older versions stored every setting, as a string.
+pub fn from_str_or_value<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + DeserializeOwned,
+    T::Err: Display,
+{
+    match toml::Value::deserialize(deserializer)? {
+        toml::Value::String(value) => value
+            .parse()
+            .map_err(|error| de::Error::custom(format!("invalid value `{value}`: {error}"))),
+        value => T::deserialize(value).map_err(de::Error::custom),
+    }
+}
 
-    pub fn get_configs(provider: &str) -> Result<HashMap<String, String>, String> {
-        let llm_config: LlmConfig = confy::load("llm-lsp", None).unwrap();
-        match provider {
-            "codeium" => Ok(llm_config.codeium),
-            _ => Err("Provider {provider} is not supported as of now!".to_owned()),
+/// [`from_str_or_value`] for optional settings.
+pub fn option_from_str_or_value<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + DeserializeOwned,
+    T::Err: Display,
+{
+    from_str_or_value(deserializer).map(Some)
+}
+
+/// Keys beyond a config's declared fields, such as a misspelled
+/// `TEMPRATURE`, kept so that rewriting the config file does not drop them.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UnknownKeys(pub Table);
+
+impl UnknownKeys {
+    pub fn check(&self) -> Result<(), String> {
+        match self.0.keys().map(String::as_str).collect::<Vec<_>>()[..] {
+            [] => Ok(()),
+            [key] => Err(format!("Unknown key {key}")),
+            ref keys => Err(format!("Unknown keys {}", keys.join(", "))),
         }
     }
 }
+
+/// Checks that `url` is an absolute URL, naming `key` otherwise.
+pub fn validate_url(key: &str, url: &str) -> Result<(), String> {
+    Url::parse(url)
+        .map(|_| ())
+        .map_err(|error| format!("{key} is not a valid URL ({error}): {url}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baseline_config() {
+        // written by older versions, with every value a string
+        let llm_config: LlmConfig = toml::from_str(
+            r#"
+            [codeium]
+            API_KEY = "key"
+            SESSION_ID = "session"
+            TELEMETRY = "false"
+            OTHER_DOCUMENTS_BUDGET = "1024"
+
+            [ollama]
+            MODEL = "qwen2.5-coder:1.5b"
+            TEMPERATURE = "0.2"
+            NUM_PREDICT = "128"
+
+            [openapi]
+            BASE_URL = "http://localhost:8080/v1"
+            MODEL = "local"
+            MAX_TOKENS = "256"
+
+            [copilot]
+            "#,
+        )
+        .unwrap();
+        llm_config.codeium.validate().unwrap();
+        assert!(!llm_config.codeium.telemetry);
+        assert_eq!(llm_config.codeium.other_documents_budget, 1024);
+        llm_config.ollama.validate().unwrap();
+        assert_eq!(llm_config.ollama.temperature, Some(0.2));
+        assert_eq!(llm_config.ollama.num_predict, Some(128));
+        llm_config.openai.validate().unwrap();
+        assert_eq!(llm_config.openai.base_url, "http://localhost:8080/v1");
+        assert_eq!(llm_config.openai.max_tokens, Some(256));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let ollama: OllamaConfig = toml::from_str(
+            r#"
+            model = "qwen2.5-coder:1.5b"
+            TEMPRATURE = 0.2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            ollama.validate().unwrap_err(),
+            "Unknown keys TEMPRATURE, model"
+        );
+    }
+}
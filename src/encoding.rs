@@ -0,0 +1,217 @@
+use async_lsp::lsp_types::{ClientCapabilities, Position, PositionEncodingKind};
+use ropey::{Rope, RopeSlice};
+
+/// Unit in which `Position::character` is counted, negotiated with the client
+/// during `initialize`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PositionEncoding {
+    Utf8,
+    /// Mandatory for every client and the default when none is negotiated.
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the first encoding in the client's preference list that we
+    /// support, falling back to UTF-16.
+    pub fn negotiate(capabilities: &ClientCapabilities) -> Self {
+        capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .and_then(|encodings| encodings.iter().find_map(Self::from_kind))
+            .unwrap_or_default()
+    }
+
+    fn from_kind(kind: &PositionEncodingKind) -> Option<Self> {
+        match kind.as_str() {
+            "utf-8" => Some(PositionEncoding::Utf8),
+            "utf-16" => Some(PositionEncoding::Utf16),
+            "utf-32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn char_to_unit(self, text: &Rope, char_idx: usize) -> usize {
+        match self {
+            PositionEncoding::Utf8 => text.char_to_byte(char_idx),
+            PositionEncoding::Utf16 => text.char_to_utf16_cu(char_idx),
+            PositionEncoding::Utf32 => char_idx,
+        }
+    }
+
+    fn unit_to_char(self, text: &Rope, unit: usize) -> usize {
+        match self {
+            PositionEncoding::Utf8 => text.byte_to_char(unit),
+            PositionEncoding::Utf16 => text.utf16_cu_to_char(unit),
+            PositionEncoding::Utf32 => unit,
+        }
+    }
+
+    /// Converts `position` to a char index into `text`. Positions past the
+    /// end of a line or of the document are clamped, as the specification
+    /// requires, and positions inside a character snap to its start.
+    pub fn position_to_char(self, text: &Rope, position: Position) -> usize {
+        let line_idx = position.line as usize;
+        if line_idx >= text.len_lines() {
+            return text.len_chars();
+        }
+        let line_start = text.line_to_char(line_idx);
+        let line_end = line_start + line_len(text.line(line_idx));
+        let unit = (self.char_to_unit(text, line_start) + position.character as usize)
+            .min(self.char_to_unit(text, line_end));
+        self.unit_to_char(text, unit)
+    }
+
+    /// Converts `position` to a UTF-8 byte offset into `text`, the unit
+    /// providers slice documents with.
+    pub fn position_to_byte(self, text: &Rope, position: Position) -> usize {
+        text.char_to_byte(self.position_to_char(text, position))
+    }
+
+    /// Converts a UTF-8 byte offset into `text` back to an LSP position.
+    pub fn byte_to_position(self, text: &Rope, byte_idx: usize) -> Position {
+        let char_idx = text.byte_to_char(byte_idx.min(text.len_bytes()));
+        let line_idx = text.char_to_line(char_idx);
+        let line_start = text.line_to_char(line_idx);
+        Position {
+            line: line_idx as u32,
+            character: (self.char_to_unit(text, char_idx) - self.char_to_unit(text, line_start))
+                as u32,
+        }
+    }
+}
+
+/// Length of `line` in chars without its line break, which is `\n`, `\r\n`
+/// or `\r` for both ropey and LSP.
+fn line_len(line: RopeSlice) -> usize {
+    let mut len = line.len_chars();
+    if len > 0 && line.char(len - 1) == '\n' {
+        len -= 1;
+    }
+    if len > 0 && line.char(len - 1) == '\r' {
+        len -= 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_lsp::lsp_types::GeneralClientCapabilities;
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn negotiate_prefers_client_order() {
+        let capabilities = ClientCapabilities {
+            general: Some(GeneralClientCapabilities {
+                position_encodings: Some(vec![
+                    PositionEncodingKind::new("utf-7"),
+                    PositionEncodingKind::UTF32,
+                    PositionEncodingKind::UTF8,
+                ]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            PositionEncoding::negotiate(&capabilities),
+            PositionEncoding::Utf32
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(&ClientCapabilities::default()),
+            PositionEncoding::Utf16
+        );
+    }
+
+    #[test]
+    fn crlf_line_breaks() {
+        let text = Rope::from_str("fn main() {\r\n    let x = 1;\r\n}\r\n");
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            assert_eq!(encoding.position_to_byte(&text, position(1, 4)), 17);
+            // past the end of the line clamps before `\r\n`
+            assert_eq!(encoding.position_to_byte(&text, position(0, 80)), 11);
+            assert_eq!(encoding.position_to_byte(&text, position(2, 1)), 30);
+            assert_eq!(encoding.position_to_byte(&text, position(9, 0)), 32);
+            assert_eq!(encoding.byte_to_position(&text, 17), position(1, 4));
+        }
+    }
+
+    #[test]
+    fn emoji_outside_the_bmp() {
+        // 😀 is 4 bytes, 2 UTF-16 code units and 1 char
+        let text = Rope::from_str("let s = \"😀\";\nx");
+        let after_emoji = 13;
+        assert_eq!(
+            PositionEncoding::Utf8.position_to_byte(&text, position(0, 13)),
+            after_emoji
+        );
+        assert_eq!(
+            PositionEncoding::Utf16.position_to_byte(&text, position(0, 11)),
+            after_emoji
+        );
+        assert_eq!(
+            PositionEncoding::Utf32.position_to_byte(&text, position(0, 10)),
+            after_emoji
+        );
+        // the middle of the surrogate pair snaps to the start of the emoji
+        assert_eq!(
+            PositionEncoding::Utf16.position_to_byte(&text, position(0, 10)),
+            9
+        );
+        assert_eq!(
+            PositionEncoding::Utf16.byte_to_position(&text, after_emoji),
+            position(0, 11)
+        );
+        assert_eq!(
+            PositionEncoding::Utf16.position_to_byte(&text, position(1, 1)),
+            17
+        );
+    }
+
+    #[test]
+    fn cjk_text() {
+        // each ideograph is 3 bytes, 1 UTF-16 code unit and 1 char
+        let text = Rope::from_str("// 你好世界\nprint()");
+        assert_eq!(
+            PositionEncoding::Utf8.position_to_byte(&text, position(0, 9)),
+            9
+        );
+        assert_eq!(
+            PositionEncoding::Utf16.position_to_byte(&text, position(0, 5)),
+            9
+        );
+        assert_eq!(
+            PositionEncoding::Utf32.position_to_byte(&text, position(0, 5)),
+            9
+        );
+        assert_eq!(
+            PositionEncoding::Utf16.byte_to_position(&text, 15),
+            position(0, 7)
+        );
+        assert_eq!(
+            PositionEncoding::Utf8.byte_to_position(&text, 15),
+            position(0, 15)
+        );
+        assert_eq!(
+            PositionEncoding::Utf16.position_to_byte(&text, position(1, 5)),
+            21
+        );
+    }
+}
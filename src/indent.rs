@@ -0,0 +1,419 @@
+use ropey::Rope;
+use std::{fs, path::Path};
+
+/// How a document is indented, in the terms of LSP's `FormattingOptions`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndentStyle {
+    pub tab_size: u32,
+    pub insert_spaces: bool,
+}
+
+impl IndentStyle {
+    /// Picks the indentation for a document: `.editorconfig` settings win,
+    /// then whatever the document already uses, then the usual style of
+    /// its language.
+    pub fn resolve(editorconfig: EditorConfigIndent, text: &Rope, language_id: &str) -> Self {
+        let fallback = Self::detect(text).unwrap_or_else(|| Self::language_default(language_id));
+        let insert_spaces = editorconfig.insert_spaces.unwrap_or(fallback.insert_spaces);
+        let tab_size = if insert_spaces {
+            editorconfig.indent_size.or(editorconfig.tab_width)
+        } else {
+            editorconfig.tab_width.or(editorconfig.indent_size)
+        };
+        IndentStyle {
+            tab_size: tab_size.unwrap_or(fallback.tab_size),
+            insert_spaces,
+        }
+    }
+
+    fn language_default(language_id: &str) -> Self {
+        match language_id {
+            "go" | "makefile" => IndentStyle {
+                tab_size: 4,
+                insert_spaces: false,
+            },
+            "css" | "dart" | "elixir" | "html" | "javascript" | "javascriptreact" | "json"
+            | "jsonc" | "less" | "lua" | "nix" | "ruby" | "scss" | "svelte" | "tsx"
+            | "typescript" | "typescriptreact" | "vue" | "yaml" => IndentStyle {
+                tab_size: 2,
+                insert_spaces: true,
+            },
+            _ => IndentStyle {
+                tab_size: 4,
+                insert_spaces: true,
+            },
+        }
+    }
+
+    /// Guesses the indentation from the first lines of `text`: tabs when
+    /// more lines start with a tab than with spaces, otherwise the most
+    /// common step between the indents of consecutive lines.
+    fn detect(text: &Rope) -> Option<Self> {
+        let mut tab_lines = 0;
+        let mut space_lines = 0;
+        let mut steps = [0usize; 9];
+        let mut previous = 0;
+        for line in text.lines().take(1000) {
+            if line.chars().all(char::is_whitespace) {
+                continue;
+            }
+            match line.char(0) {
+                '\t' => tab_lines += 1,
+                ' ' => space_lines += 1,
+                _ => {}
+            }
+            let indent = line.chars().take_while(|c| *c == ' ').count();
+            if indent > previous && indent - previous < steps.len() {
+                steps[indent - previous] += 1;
+            }
+            previous = indent;
+        }
+        if tab_lines > space_lines {
+            return Some(IndentStyle {
+                tab_size: 4,
+                insert_spaces: false,
+            });
+        }
+        // single-space steps are alignment rather than indentation; ties go
+        // to the smaller step, as 4-space code rarely indents by 2
+        let (step, _) = steps
+            .iter()
+            .enumerate()
+            .skip(2)
+            .filter(|(_, count)| **count > 0)
+            .max_by_key(|(step, count)| (**count, usize::MAX - step))?;
+        Some(IndentStyle {
+            tab_size: step as u32,
+            insert_spaces: true,
+        })
+    }
+}
+
+/// Indentation properties from `.editorconfig` files, each unset when no
+/// section matching the file sets it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EditorConfigIndent {
+    pub insert_spaces: Option<bool>,
+    pub indent_size: Option<u32>,
+    pub tab_width: Option<u32>,
+}
+
+#[derive(Clone, Copy)]
+enum IndentSize {
+    Columns(u32),
+    /// `indent_size = tab`, which defers to `tab_width`.
+    Tab,
+}
+
+/// Properties set by the sections of one `.editorconfig` file that match.
+#[derive(Default)]
+struct EditorConfigFile {
+    root: bool,
+    insert_spaces: Option<bool>,
+    indent_size: Option<IndentSize>,
+    tab_width: Option<u32>,
+}
+
+impl EditorConfigIndent {
+    /// Reads the `.editorconfig` files from the directory of `path` upwards,
+    /// until one of them is marked `root = true`.
+    pub fn load(path: &Path) -> Self {
+        let mut insert_spaces = None;
+        let mut indent_size = None;
+        let mut tab_width = None;
+        for dir in path.ancestors().skip(1) {
+            let Ok(contents) = fs::read_to_string(dir.join(".editorconfig")) else {
+                continue;
+            };
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let file =
+                EditorConfigFile::parse(&contents, &relative.to_string_lossy().replace('\\', "/"));
+            // closer files take precedence
+            insert_spaces = insert_spaces.or(file.insert_spaces);
+            indent_size = indent_size.or(file.indent_size);
+            tab_width = tab_width.or(file.tab_width);
+            if file.root {
+                break;
+            }
+        }
+        EditorConfigIndent {
+            insert_spaces,
+            indent_size: match indent_size {
+                Some(IndentSize::Columns(columns)) => Some(columns),
+                Some(IndentSize::Tab) => tab_width,
+                None => None,
+            },
+            tab_width,
+        }
+    }
+}
+
+impl EditorConfigFile {
+    /// Collects the properties of the sections matching `path`, with later
+    /// sections overriding earlier ones.
+    fn parse(contents: &str, path: &str) -> Self {
+        let mut file = EditorConfigFile::default();
+        let mut section_matches = false;
+        let mut in_preamble = true;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_preamble = false;
+                section_matches = section_matches_path(section, path);
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_lowercase();
+            if in_preamble {
+                file.root |= key == "root" && value == "true";
+                continue;
+            }
+            if !section_matches {
+                continue;
+            }
+            match (key.as_str(), value.as_str()) {
+                ("indent_style", "space") => file.insert_spaces = Some(true),
+                ("indent_style", "tab") => file.insert_spaces = Some(false),
+                ("indent_size", "tab") => file.indent_size = Some(IndentSize::Tab),
+                ("indent_size", size) => {
+                    if let Ok(size) = size.parse() {
+                        file.indent_size = Some(IndentSize::Columns(size));
+                    }
+                }
+                ("tab_width", width) => {
+                    if let Ok(width) = width.parse() {
+                        file.tab_width = Some(width);
+                    }
+                }
+                _ => {}
+            }
+        }
+        file
+    }
+}
+
+/// Matches an `.editorconfig` section glob against `path`, which is relative
+/// to the directory of the `.editorconfig` file and uses `/` separators.
+fn section_matches_path(section: &str, path: &str) -> bool {
+    let pattern = match section.strip_prefix('/') {
+        Some(anchored) => anchored.to_owned(),
+        None if section.contains('/') => section.to_owned(),
+        // globs without a separator match the file name in any directory
+        None => format!("**/{section}"),
+    };
+    expand_braces(&pattern)
+        .iter()
+        .any(|pattern| glob_matches(pattern.as_bytes(), path.as_bytes()))
+}
+
+/// Expands `{a,b}` alternatives into separate patterns.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(open) = pattern.find('{') else {
+        return vec![pattern.to_owned()];
+    };
+    let mut depth = 0;
+    let mut alternatives = vec![];
+    let mut start = open + 1;
+    for (idx, c) in pattern[open..].char_indices().map(|(i, c)| (i + open, c)) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    alternatives.push(&pattern[start..idx]);
+                    let (prefix, suffix) = (&pattern[..open], &pattern[idx + 1..]);
+                    return alternatives
+                        .into_iter()
+                        .flat_map(|alternative| {
+                            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+                        })
+                        .collect();
+                }
+            }
+            ',' if depth == 1 => {
+                alternatives.push(&pattern[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    // an unclosed brace is matched literally
+    vec![pattern.to_owned()]
+}
+
+/// Matches `*`, `**`, `?` and `[...]` wildcards; only `**` crosses `/`.
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // `**/` also matches no directory at all
+            glob_matches(rest, path)
+                || (0..path.len()).any(|idx| glob_matches(&pattern[2..], &path[idx..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|idx| glob_matches(rest, &path[idx..])),
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|&idx| idx == 0 || path[idx - 1] != b'/')
+            .any(|idx| glob_matches(rest, &path[idx..])),
+        [b'?', rest @ ..] => {
+            matches!(path, [c, ..] if *c != b'/') && glob_matches(rest, &path[1..])
+        }
+        [b'[', class @ ..] => match class.iter().position(|&c| c == b']') {
+            Some(end) if !path.is_empty() => {
+                let (negated, set) = match &class[..end] {
+                    [b'!', set @ ..] => (true, set),
+                    set => (false, set),
+                };
+                let matched = set_contains(set, path[0]);
+                path[0] != b'/' && matched != negated && glob_matches(&class[end + 1..], &path[1..])
+            }
+            Some(_) => false,
+            None => path.first() == Some(&b'[') && glob_matches(class, &path[1..]),
+        },
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => {
+            path.first() == Some(c) && glob_matches(rest, &path[1..])
+        }
+    }
+}
+
+fn set_contains(set: &[u8], c: u8) -> bool {
+    let mut idx = 0;
+    while idx < set.len() {
+        if idx + 2 < set.len() && set[idx + 1] == b'-' {
+            if (set[idx]..=set[idx + 2]).contains(&c) {
+                return true;
+            }
+            idx += 3;
+        } else {
+            if set[idx] == c {
+                return true;
+            }
+            idx += 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spaces(tab_size: u32) -> IndentStyle {
+        IndentStyle {
+            tab_size,
+            insert_spaces: true,
+        }
+    }
+
+    #[test]
+    fn detect_most_common_step() {
+        let text = Rope::from_str("fn a() {\n  if x {\n    y();\n  }\n}\nfn b() {\n  z();\n}\n");
+        assert_eq!(IndentStyle::detect(&text), Some(spaces(2)));
+        // a continuation line aligned by one space does not count
+        let text = Rope::from_str("a:\n    b = 1 +\n     2\n    c\n        d\n");
+        assert_eq!(IndentStyle::detect(&text), Some(spaces(4)));
+        let text = Rope::from_str("a {\n\tb;\n\tc {\n\t\td;\n\t}\n}\n");
+        assert_eq!(
+            IndentStyle::detect(&text),
+            Some(IndentStyle {
+                tab_size: 4,
+                insert_spaces: false,
+            })
+        );
+        assert_eq!(IndentStyle::detect(&Rope::from_str("a\nb\n")), None);
+    }
+
+    #[test]
+    fn resolve_precedence() {
+        let text = Rope::from_str("a {\n  b;\n}\n");
+        assert_eq!(
+            IndentStyle::resolve(EditorConfigIndent::default(), &text, "rust"),
+            spaces(2)
+        );
+        assert_eq!(
+            IndentStyle::resolve(EditorConfigIndent::default(), &Rope::new(), "rust"),
+            spaces(4)
+        );
+        assert!(
+            !IndentStyle::resolve(EditorConfigIndent::default(), &Rope::new(), "go").insert_spaces
+        );
+        let editorconfig = EditorConfigIndent {
+            insert_spaces: None,
+            indent_size: Some(8),
+            tab_width: None,
+        };
+        assert_eq!(IndentStyle::resolve(editorconfig, &text, "rust"), spaces(8));
+    }
+
+    #[test]
+    fn expand_braces_alternatives() {
+        assert_eq!(expand_braces("*.rs"), vec!["*.rs"]);
+        assert_eq!(expand_braces("*.{js,ts}"), vec!["*.js", "*.ts"]);
+        assert_eq!(
+            expand_braces("{a,b}/*.{c,d}"),
+            vec!["a/*.c", "a/*.d", "b/*.c", "b/*.d"]
+        );
+        assert_eq!(expand_braces("{a,{b,c}}"), vec!["a", "b", "c"]);
+        assert_eq!(expand_braces("{a,b"), vec!["{a,b"]);
+    }
+
+    #[test]
+    fn section_globs() {
+        assert!(section_matches_path("*", "main.rs"));
+        assert!(section_matches_path("*.rs", "src/main.rs"));
+        assert!(!section_matches_path("*.rs", "src/main.py"));
+        assert!(section_matches_path("*.{js,ts}", "web/app.ts"));
+        // a separator anchors the glob to the `.editorconfig` directory
+        assert!(section_matches_path("src/*.rs", "src/main.rs"));
+        assert!(!section_matches_path("src/*.rs", "lib/src/main.rs"));
+        assert!(!section_matches_path("src/*.rs", "src/bin/main.rs"));
+        assert!(section_matches_path("/Makefile", "Makefile"));
+        assert!(!section_matches_path("/Makefile", "sub/Makefile"));
+        assert!(section_matches_path("src/**.rs", "src/bin/main.rs"));
+        assert!(section_matches_path("**/tests/*.rs", "tests/a.rs"));
+        assert!(section_matches_path("file?.txt", "file1.txt"));
+        assert!(!section_matches_path("file?.txt", "file10.txt"));
+        assert!(section_matches_path("[Mm]akefile", "makefile"));
+        assert!(section_matches_path("*.[a-c]", "x.b"));
+        assert!(!section_matches_path("*.[!a-c]", "x.b"));
+    }
+
+    #[test]
+    fn parse_matching_sections() {
+        let contents = "\
+root = true
+
+[*]
+indent_style = space
+indent_size = 4
+
+# later sections override earlier ones
+[*.go]
+indent_style = tab
+tab_width = 8
+
+[Makefile]
+indent_size = tab
+";
+        let file = EditorConfigFile::parse(contents, "cmd/main.go");
+        assert!(file.root);
+        assert_eq!(file.insert_spaces, Some(false));
+        assert_eq!(file.tab_width, Some(8));
+        assert!(matches!(file.indent_size, Some(IndentSize::Columns(4))));
+
+        let file = EditorConfigFile::parse(contents, "Makefile");
+        assert_eq!(file.insert_spaces, Some(true));
+        assert!(matches!(file.indent_size, Some(IndentSize::Tab)));
+
+        // `root` only counts before the first section
+        let file = EditorConfigFile::parse("[*]\nroot = true\n", "a.rs");
+        assert!(!file.root);
+    }
+}
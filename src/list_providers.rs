@@ -0,0 +1,81 @@
+use crate::{
+    configs::LlmConfig,
+    providers::{self, Capabilities},
+};
+use serde::Serialize;
+
+/// A registered provider as `llm-lsp list-providers` reports it.
+#[derive(Serialize)]
+struct ProviderInfo {
+    name: &'static str,
+    /// Whether the provider's table holds everything it needs.
+    configured: bool,
+    model: Option<String>,
+    capabilities: Capabilities,
+    /// Profiles that use the provider.
+    profiles: Vec<String>,
+}
+
+pub fn run(json: bool) -> Result<(), String> {
+    let llm_config = LlmConfig::load()?;
+    let mut infos = vec![];
+    for provider in providers::PROVIDERS {
+        let section = llm_config.get_configs(provider.name)?;
+        infos.push(ProviderInfo {
+            name: provider.name,
+            configured: provider.is_configured(section.clone()),
+            model: section
+                .get("MODEL")
+                .and_then(|model| model.as_str())
+                .map(str::to_owned),
+            capabilities: provider.capabilities,
+            profiles: llm_config
+                .profiles
+                .iter()
+                .filter(|(_, profile)| profile.provider == provider.name)
+                .map(|(name, _)| name.clone())
+                .collect(),
+        });
+    }
+
+    if json {
+        let output = serde_json::to_string_pretty(&infos).map_err(|error| error.to_string())?;
+        println!("{output}");
+        return Ok(());
+    }
+    let rows: Vec<[String; 5]> = infos
+        .into_iter()
+        .map(|info| {
+            [
+                info.name.to_owned(),
+                if info.configured { "yes" } else { "no" }.to_owned(),
+                info.model.unwrap_or_else(|| "-".to_owned()),
+                info.capabilities.names().join(", "),
+                info.profiles.join(", "),
+            ]
+        })
+        .collect();
+    let header = [
+        "PROVIDER",
+        "CONFIGURED",
+        "MODEL",
+        "CAPABILITIES",
+        "PROFILES",
+    ]
+    .map(str::to_owned);
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| format!("{column:width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+    Ok(())
+}
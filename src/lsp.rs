@@ -1,25 +1,49 @@
 use crate::{
-    providers::llm_api::{CompletionRequest, LlmClientApi},
-    server::LlmLanguageServer,
+    configs::{CommandOutput, ACCEPT_COMPLETION_COMMAND, COMMAND_SYSTEM_PROMPT},
+    encoding::PositionEncoding,
+    providers::llm_api::{
+        strip_code_fence, ChatMessage, ChatRequest, CodeContext, CompletionRequest,
+    },
+    providers::Selection,
+    server::{CompletionKind, LlmLanguageServer, ReloadEvent},
 };
 use async_lsp::{
     lsp_types::{
-        CodeActionParams, CodeActionProviderCapability, CodeActionResponse, CompletionOptions,
-        CompletionParams, CompletionResponse, DidChangeConfigurationParams,
-        DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-        ExecuteCommandOptions, InitializeParams, InitializeResult, ServerCapabilities,
-        TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions, Url,
+        ApplyWorkspaceEditParams, CodeAction, CodeActionKind, CodeActionOrCommand,
+        CodeActionParams, CodeActionProviderCapability, CodeActionResponse, Command as LspCommand,
+        CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams,
+        CompletionResponse, CompletionTextEdit, DidChangeConfigurationParams,
+        DidChangeTextDocumentParams, DidChangeWorkspaceFoldersParams, DidCloseTextDocumentParams,
+        DidOpenTextDocumentParams, DocumentChanges, ExecuteCommandOptions, ExecuteCommandParams,
+        InitializeParams, InitializeResult, InlineCompletionItem, InlineCompletionParams,
+        InlineCompletionResponse, MessageType, OneOf, OptionalVersionedTextDocumentIdentifier,
+        Range, ServerCapabilities, ServerInfo, ShowMessageParams, TextDocumentEdit,
+        TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind,
+        TextDocumentSyncOptions, TextEdit, Url, WorkspaceEdit, WorkspaceFolder,
+        WorkspaceFoldersServerCapabilities, WorkspaceServerCapabilities,
     },
-    LanguageServer, ResponseError,
+    ErrorCode, LanguageClient, LanguageServer, ResponseError,
 };
 use futures::future::BoxFuture;
-use std::ops::ControlFlow;
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{ops::ControlFlow, sync::Arc};
+use tokio_util::sync::CancellationToken;
+use toml::Table;
+use tracing::{info, warn};
+
+/// The single argument of every `workspace/executeCommand` issued by llm-lsp.
+#[derive(Serialize, Deserialize)]
+pub struct CommandArguments {
+    pub uri: Url,
+    pub range: Range,
+    /// Diagnostics the editor reported for the selection, e.g.
+    /// `line 3: rustc: mismatched types`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<String>,
+}
 
-impl<'a, T> LanguageServer for LlmLanguageServer<'a, T>
-where
-    T: LlmClientApi,
-{
+impl LanguageServer for LlmLanguageServer<'_> {
     type Error = ResponseError;
     type NotifyResult = ControlFlow<async_lsp::Result<()>>;
 
@@ -32,7 +56,32 @@ where
             .iter()
             .map(|&s| (*s).into())
             .collect();
-        let commands = self.commands.iter().map(|c| c.key.to_owned()).collect();
+        let commands = self
+            .commands
+            .iter()
+            .map(|c| c.key)
+            .chain([ACCEPT_COMPLETION_COMMAND])
+            .map(str::to_owned)
+            .collect();
+        self.state.position_encoding = PositionEncoding::negotiate(&params.capabilities);
+        #[allow(deprecated)]
+        let root_uri = params.root_uri;
+        // `rootUri` is only a fallback for clients without workspace folders
+        let workspace_folders = params.workspace_folders.unwrap_or_else(|| {
+            root_uri
+                .map(|uri| WorkspaceFolder {
+                    name: uri
+                        .path_segments()
+                        .and_then(|mut segments| segments.next_back())
+                        .unwrap_or_default()
+                        .to_owned(),
+                    uri,
+                })
+                .into_iter()
+                .collect()
+        });
+        self.state.set_workspace_folders(workspace_folders);
+        let position_encoding = self.state.position_encoding.kind();
         let unknown = "unknown".to_owned();
         if let Some(client_info) = params.client_info {
             let client_version = client_info.version.unwrap_or(unknown);
@@ -41,9 +90,18 @@ where
         } else {
             self.state.update_client_info("web".to_owned(), unknown);
         };
+        // no request holds the client yet, so it can still be changed in place
+        if let Some(llm_client) = Arc::get_mut(&mut self.llm_client) {
+            llm_client.set_client_info(&self.state.client_info);
+        }
+        if let Some(options) = params.initialization_options {
+            self.apply_settings(options);
+        }
         Box::pin(async move {
             Ok(InitializeResult {
                 capabilities: ServerCapabilities {
+                    position_encoding: Some(position_encoding),
+                    inline_completion_provider: Some(OneOf::Left(true)),
                     code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
                     completion_provider: Some(CompletionOptions {
                         resolve_provider: Some(false),
@@ -54,46 +112,73 @@ where
                         commands,
                         ..Default::default()
                     }),
+                    workspace: Some(WorkspaceServerCapabilities {
+                        workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                            supported: Some(true),
+                            change_notifications: Some(OneOf::Left(true)),
+                        }),
+                        file_operations: None,
+                    }),
                     text_document_sync: Some(TextDocumentSyncCapability::Options(
                         TextDocumentSyncOptions {
-                            change: Some(TextDocumentSyncKind::FULL),
+                            change: Some(TextDocumentSyncKind::INCREMENTAL),
                             open_close: Some(true),
                             ..Default::default()
                         },
                     )),
                     ..Default::default()
                 },
-                server_info: None,
+                server_info: Some(ServerInfo {
+                    name: env!("CARGO_PKG_NAME").to_owned(),
+                    version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+                }),
+                offset_encoding: None,
             })
         })
     }
 
     fn did_change_configuration(
         &mut self,
-        _: DidChangeConfigurationParams,
+        params: DidChangeConfigurationParams,
     ) -> ControlFlow<async_lsp::Result<()>> {
+        self.apply_settings(params.settings);
         ControlFlow::Continue(())
     }
 
-    fn did_open(&mut self, params: DidOpenTextDocumentParams) -> Self::NotifyResult {
-        let uri = params.text_document.uri;
-        let content = params.text_document.text;
-        let language_id = params.text_document.language_id;
+    fn did_change_workspace_folders(
+        &mut self,
+        params: DidChangeWorkspaceFoldersParams,
+    ) -> Self::NotifyResult {
+        self.state.update_workspace_folders(params.event);
+        ControlFlow::Continue(())
+    }
 
-        self.state.upsert_file(&uri, content, Some(language_id));
+    fn did_open(&mut self, params: DidOpenTextDocumentParams) -> Self::NotifyResult {
+        let doc = params.text_document;
+        self.state
+            .open_document(&doc.uri, &doc.text, doc.language_id, doc.version);
         ControlFlow::Continue(())
     }
 
     fn did_change(&mut self, params: DidChangeTextDocumentParams) -> Self::NotifyResult {
         let uri = params.text_document.uri;
-        if !params.content_changes.is_empty() {
-            let content = params.content_changes[0].text.clone();
-            self.state.upsert_file(&uri, content, None);
+        let version = params.text_document.version;
+        for change in params.content_changes {
+            self.state.apply_change(&uri, change, version);
         }
         ControlFlow::Continue(())
     }
 
-    fn did_close(&mut self, _params: DidCloseTextDocumentParams) -> Self::NotifyResult {
+    fn did_close(&mut self, params: DidCloseTextDocumentParams) -> Self::NotifyResult {
+        let uri = params.text_document.uri;
+        self.pending_completions
+            .retain(|(pending_uri, _), pending| {
+                if *pending_uri == uri {
+                    pending.cancel();
+                }
+                *pending_uri != uri
+            });
+        self.state.close_document(&uri);
         ControlFlow::Continue(())
     }
 
@@ -106,37 +191,181 @@ where
                 // info!("trigger: {trigger_character}");
             }
         }
-        let filepath = params
-            .text_document_position
-            .text_document
-            .uri
-            .path()
-            .to_owned();
-        let position_line = params.text_document_position.position.line;
-        let position_char = params.text_document_position.position.character;
-        let contents = self
-            .state
-            .get_contents(&Url::from_file_path(&filepath).unwrap());
-        let language_id = self
-            .state
-            .get_language_id(&Url::from_file_path(&filepath).unwrap());
-        self.llm_client.completion(CompletionRequest {
-            contents,
-            filepath,
-            language_id,
-            position_line,
-            position_char,
-            suggestions: 3,
-            client_name: self.state.client_info.name.clone(),
-            client_version: self.state.client_info.version.clone(),
+        let suggestions = self.suggest(params.text_document_position, CompletionKind::Menu);
+        Box::pin(async move {
+            let Some(suggestions) = suggestions.await? else {
+                return Ok(None);
+            };
+            let items = suggestions
+                .into_iter()
+                .map(|suggestion| CompletionItem {
+                    label: suggestion.text.trim().to_owned(),
+                    kind: Some(CompletionItemKind::TEXT),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range: suggestion.range,
+                        new_text: suggestion.text,
+                    })),
+                    command: suggestion.id.map(accept_command),
+                    ..Default::default()
+                })
+                .collect();
+            Ok(Some(CompletionResponse::Array(items)))
         })
     }
 
     fn code_action(
         &mut self,
-        _params: CodeActionParams,
+        params: CodeActionParams,
     ) -> BoxFuture<'static, Result<Option<CodeActionResponse>, ResponseError>> {
-        Box::pin(async move { Ok(None) })
+        let range = params.range;
+        // Commands work on the selection, so there is nothing to offer without one
+        if range.start == range.end {
+            return Box::pin(async move { Ok(None) });
+        }
+        let diagnostics = params
+            .context
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let source = diagnostic
+                    .source
+                    .map(|source| format!("{source}: "))
+                    .unwrap_or_default();
+                let line = diagnostic.range.start.line + 1;
+                format!("line {line}: {source}{}", diagnostic.message)
+            })
+            .collect();
+        let arguments = serde_json::to_value(CommandArguments {
+            uri: params.text_document.uri,
+            range,
+            diagnostics,
+        })
+        .ok();
+        let actions = self
+            .commands
+            .iter()
+            .map(|command| {
+                CodeActionOrCommand::CodeAction(CodeAction {
+                    title: command.label.to_owned(),
+                    kind: Some(CodeActionKind::REFACTOR_REWRITE),
+                    command: Some(LspCommand {
+                        title: command.label.to_owned(),
+                        command: command.key.to_owned(),
+                        arguments: arguments.clone().map(|arguments| vec![arguments]),
+                    }),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        Box::pin(async move { Ok(Some(actions)) })
+    }
+
+    fn execute_command(
+        &mut self,
+        params: ExecuteCommandParams,
+    ) -> BoxFuture<'static, Result<Option<Value>, ResponseError>> {
+        if params.command == ACCEPT_COMPLETION_COMMAND {
+            let Some(Value::String(id)) = params.arguments.into_iter().next() else {
+                return Box::pin(async move {
+                    Err(ResponseError::new(
+                        ErrorCode::INVALID_PARAMS,
+                        "Expected the completion id as command argument",
+                    ))
+                });
+            };
+            let accept = self.llm_client.accept_completion(id);
+            return Box::pin(async move {
+                // acceptance is best effort and must not bother the user
+                if let Err(error) = accept.await {
+                    warn!("accept completion error: {}", error.message);
+                }
+                Ok(None)
+            });
+        }
+        let Some(command) = self.commands.iter().find(|c| c.key == params.command) else {
+            let message = format!("Unknown command: {}", params.command);
+            return Box::pin(
+                async move { Err(ResponseError::new(ErrorCode::INVALID_PARAMS, message)) },
+            );
+        };
+        let arguments = match params
+            .arguments
+            .into_iter()
+            .next()
+            .map(serde_json::from_value::<CommandArguments>)
+        {
+            Some(Ok(arguments)) => arguments,
+            _ => {
+                return Box::pin(async move {
+                    Err(ResponseError::new(
+                        ErrorCode::INVALID_PARAMS,
+                        "Expected `{ uri, range }` as command argument",
+                    ))
+                })
+            }
+        };
+        let Some(code) = self.state.get_range(&arguments.uri, &arguments.range) else {
+            let message = format!("Document is not open: {}", arguments.uri);
+            return Box::pin(
+                async move { Err(ResponseError::new(ErrorCode::INVALID_PARAMS, message)) },
+            );
+        };
+        let mut query = command.query.to_owned();
+        if !arguments.diagnostics.is_empty() {
+            query.push_str("\n\nDiagnostics:");
+            for diagnostic in &arguments.diagnostics {
+                query.push_str(&format!("\n- {diagnostic}"));
+            }
+        }
+        let chat = self.llm_client.chat(ChatRequest {
+            system_prompt: Some(COMMAND_SYSTEM_PROMPT.to_owned()),
+            messages: vec![ChatMessage::user(query)],
+            context: Some(CodeContext {
+                filepath: self.state.relative_path(&arguments.uri),
+                language_id: self.state.get_language_id(&arguments.uri),
+                code,
+            }),
+        });
+        let label = command.label.to_owned();
+        let output = command.output;
+        let documents = self.state.documents();
+        let version = documents.get_version(&arguments.uri);
+        let mut client = self.client.clone();
+        Box::pin(async move {
+            let answer = strip_code_fence(&chat.await?.content);
+            // the range was picked in the document as it was before the answer arrived
+            if documents.get_version(&arguments.uri) != version {
+                return Err(ResponseError::new(
+                    ErrorCode::CONTENT_MODIFIED,
+                    format!("{label}: the document changed while waiting for the answer"),
+                ));
+            }
+            let text_edit = command_edit(output, arguments.range, answer);
+            // the version lets the client refuse the edit if it is still outdated
+            let edit = WorkspaceEdit {
+                document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        uri: arguments.uri,
+                        version,
+                    },
+                    edits: vec![OneOf::Left(text_edit)],
+                }])),
+                ..Default::default()
+            };
+            let response = client
+                .apply_edit(ApplyWorkspaceEditParams {
+                    label: Some(label),
+                    edit,
+                })
+                .await
+                .map_err(|error| {
+                    ResponseError::new(ErrorCode::REQUEST_FAILED, error.to_string())
+                })?;
+            if !response.applied {
+                info!("edit not applied: {:?}", response.failure_reason);
+            }
+            Ok(None)
+        })
     }
 
     fn shutdown(&mut self, _: ()) -> BoxFuture<'static, Result<(), ResponseError>> {
@@ -144,3 +373,296 @@ where
         Box::pin(async move { Ok(()) })
     }
 }
+
+impl LlmLanguageServer<'_> {
+    /// Handles `textDocument/inlineCompletion`, which editors render as ghost text.
+    pub fn inline_completion(
+        &mut self,
+        params: InlineCompletionParams,
+    ) -> BoxFuture<'static, Result<Option<InlineCompletionResponse>, ResponseError>> {
+        let suggestions = self.suggest(params.text_document_position, CompletionKind::Inline);
+        Box::pin(async move {
+            let Some(suggestions) = suggestions.await? else {
+                return Ok(None);
+            };
+            let items = suggestions
+                .into_iter()
+                .map(|suggestion| InlineCompletionItem {
+                    insert_text: suggestion.text,
+                    filter_text: None,
+                    range: Some(suggestion.range),
+                    command: suggestion.id.map(accept_command),
+                    insert_text_format: None,
+                })
+                .collect();
+            Ok(Some(InlineCompletionResponse::Array(items)))
+        })
+    }
+
+    /// Applies settings from `initializationOptions` or
+    /// `didChangeConfiguration`. The client is rebuilt off the main loop,
+    /// since reading secrets may run a command, and arrives as a
+    /// `ReloadEvent`; the current client is kept when the settings are
+    /// invalid.
+    fn apply_settings(&mut self, settings: Value) {
+        if settings.is_null() {
+            return;
+        }
+        let (selection, overrides, completions_enabled) = match self.parse_settings(settings) {
+            Ok(parsed) => parsed,
+            Err(error) => return self.show_settings_error(error),
+        };
+        self.settings_generation += 1;
+        let generation = self.settings_generation;
+        let client = self.client.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = selection.build(overrides);
+            let _ = client.emit(ReloadEvent {
+                generation,
+                result,
+                completions_enabled,
+            });
+        });
+    }
+
+    /// Swaps in the client built by `apply_settings`.
+    pub fn on_reload(&mut self, event: ReloadEvent) -> ControlFlow<async_lsp::Result<()>> {
+        // settings sent since are still being applied
+        if event.generation != self.settings_generation {
+            return ControlFlow::Continue(());
+        }
+        match event.result {
+            Ok(mut llm_client) => {
+                llm_client.set_client_info(&self.state.client_info);
+                self.llm_client = Arc::from(llm_client);
+                self.completions_enabled = event.completions_enabled;
+                info!("applied editor settings");
+            }
+            Err(error) => self.show_settings_error(error),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn show_settings_error(&mut self, error: String) {
+        warn!("{error}");
+        let _ = self.client.show_message(ShowMessageParams {
+            typ: MessageType::ERROR,
+            message: format!("llm-lsp: {error}"),
+        });
+    }
+
+    /// Reads the provider selection, overrides and whether completions are
+    /// on from `settings`, to be merged over the config file. `provider`
+    /// and `profile` switch to another table, `completions` turns
+    /// completions off, and the keys in `EDITOR_SETTINGS` override the
+    /// provider setting of the same name.
+    fn parse_settings(&self, settings: Value) -> Result<(Selection, Table, bool), String> {
+        // settings may be scoped to the server, as in `{ "llm-lsp": { ... } }`
+        let settings = match settings {
+            Value::Object(mut settings)
+                if settings.get("llm-lsp").is_some_and(Value::is_object) =>
+            {
+                settings.remove("llm-lsp").unwrap_or_default()
+            }
+            settings => settings,
+        };
+        let Value::Object(settings) = settings else {
+            return Err("Expected the settings to be an object".to_owned());
+        };
+        let mut overrides = Table::new();
+        let mut selection = Selection::default();
+        let mut completions_enabled = true;
+        for (key, value) in settings {
+            match (key.as_str(), value) {
+                (_, Value::Null) => {}
+                ("completions", Value::Bool(enabled)) => completions_enabled = enabled,
+                ("provider", Value::String(provider)) => selection.provider = Some(provider),
+                ("profile", Value::String(profile)) => selection.profile = Some(profile),
+                ("completions" | "provider" | "profile", value) => {
+                    return Err(format!("Invalid {key} setting: {value}"));
+                }
+                (_, value) => {
+                    let value = toml::Value::try_from(&value)
+                        .map_err(|error| format!("Invalid {key} setting: {error}"))?;
+                    overrides.insert(key, value);
+                }
+            }
+        }
+        if selection.provider.is_none() && selection.profile.is_none() {
+            selection = self.selection.clone();
+        }
+        Ok((selection, overrides, completions_enabled))
+    }
+
+    /// Asks the provider for suggestions at `position`, after the debounce
+    /// window, and maps them back to editor ranges. Resolves to `None` when a
+    /// newer request of the same `kind` for the document supersedes this one
+    /// or the document changes before the provider answers.
+    fn suggest(
+        &mut self,
+        position: TextDocumentPositionParams,
+        kind: CompletionKind,
+    ) -> BoxFuture<'static, Result<Option<Vec<LspSuggestion>>, ResponseError>> {
+        if !self.completions_enabled {
+            return Box::pin(async { Ok(None) });
+        }
+        let uri = position.text_document.uri;
+        let text = self.state.get_text(&uri);
+        let position_encoding = self.state.position_encoding;
+        let cursor_offset = position_encoding.position_to_byte(&text, position.position);
+        let documents = self.state.documents();
+        let version = documents.get_version(&uri);
+        let state = self.state.clone();
+        let llm_client = self.llm_client.clone();
+        let token = CancellationToken::new();
+        if let Some(previous) = self
+            .pending_completions
+            .insert((uri.clone(), kind), token.clone())
+        {
+            previous.cancel();
+        }
+        let debounce = self.completion_debounce;
+        // `$/cancelRequest` is handled by `ConcurrencyLayer`, which drops this
+        // future and with it any in-flight provider request
+        Box::pin(async move {
+            let _finished = token.clone().drop_guard();
+            let suggestions = tokio::select! {
+                _ = token.cancelled() => {
+                    info!("superseded completion for {}", uri);
+                    return Ok(None);
+                }
+                suggestions = async {
+                    tokio::time::sleep(debounce).await;
+                    // copying the document is only worth it once the user pauses
+                    let indent = state.get_indent(&uri);
+                    llm_client
+                        .completion(CompletionRequest {
+                            contents: text.to_string(),
+                            filepath: uri.path().to_owned(),
+                            relative_path: state.relative_path(&uri),
+                            language_id: state.get_language_id(&uri),
+                            cursor_offset,
+                            suggestions: 3,
+                            tab_size: indent.tab_size,
+                            insert_spaces: indent.insert_spaces,
+                            other_documents: if llm_client.uses_other_documents() {
+                                state.get_other_documents(&uri)
+                            } else {
+                                vec![]
+                            },
+                        })
+                        .await
+                } => suggestions?,
+            };
+            // the edits were computed against the document as it was when the request started
+            if documents.get_version(&uri) != version {
+                info!("discarding stale completion for {}", uri);
+                return Ok(None);
+            }
+            let suggestions = suggestions
+                .into_iter()
+                .map(|suggestion| LspSuggestion {
+                    range: Range {
+                        start: position_encoding.byte_to_position(&text, suggestion.range.start),
+                        end: position_encoding.byte_to_position(&text, suggestion.range.end),
+                    },
+                    text: suggestion.text,
+                    id: suggestion.id,
+                })
+                .collect();
+            Ok(Some(suggestions))
+        })
+    }
+}
+
+/// A provider suggestion mapped back to editor coordinates.
+struct LspSuggestion {
+    range: Range,
+    text: String,
+    id: Option<String>,
+}
+
+/// Command the client runs once it has inserted the completion with `id`.
+fn accept_command(id: String) -> LspCommand {
+    LspCommand {
+        title: "Accept completion".to_owned(),
+        command: ACCEPT_COMPLETION_COMMAND.to_owned(),
+        arguments: Some(vec![Value::String(id)]),
+    }
+}
+
+/// The edit putting the answer to a command into the document.
+fn command_edit(output: CommandOutput, range: Range, answer: String) -> TextEdit {
+    // a selection of whole lines ends at the start of the next one
+    let whole_lines = range.end.character == 0;
+    match output {
+        CommandOutput::ReplaceSelection => TextEdit {
+            range,
+            // keep the line break the selection ends with, which models drop
+            new_text: if whole_lines && !answer.is_empty() && !answer.ends_with('\n') {
+                format!("{answer}\n")
+            } else {
+                answer
+            },
+        },
+        CommandOutput::InsertAfterSelection => TextEdit {
+            range: Range {
+                start: range.end,
+                end: range.end,
+            },
+            new_text: if whole_lines {
+                format!("\n{answer}\n")
+            } else {
+                format!("\n\n{answer}")
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_lsp::lsp_types::Position;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range {
+            start: Position::new(start.0, start.1),
+            end: Position::new(end.0, end.1),
+        }
+    }
+
+    #[test]
+    fn replace_whole_lines() {
+        let lines = range((2, 0), (4, 0));
+        let edit = command_edit(CommandOutput::ReplaceSelection, lines, "a\nb".to_owned());
+        assert_eq!(edit.range, lines);
+        assert_eq!(edit.new_text, "a\nb\n");
+        let edit = command_edit(CommandOutput::ReplaceSelection, lines, "a\nb\n".to_owned());
+        assert_eq!(edit.new_text, "a\nb\n");
+        // within a line there is no line break to keep
+        let edit = command_edit(
+            CommandOutput::ReplaceSelection,
+            range((2, 4), (2, 9)),
+            "a".to_owned(),
+        );
+        assert_eq!(edit.new_text, "a");
+    }
+
+    #[test]
+    fn insert_after_selection() {
+        let edit = command_edit(
+            CommandOutput::InsertAfterSelection,
+            range((2, 0), (4, 0)),
+            "test".to_owned(),
+        );
+        assert_eq!(edit.range, range((4, 0), (4, 0)));
+        assert_eq!(edit.new_text, "\ntest\n");
+        let edit = command_edit(
+            CommandOutput::InsertAfterSelection,
+            range((2, 0), (3, 1)),
+            "test".to_owned(),
+        );
+        assert_eq!(edit.range, range((3, 1), (3, 1)));
+        assert_eq!(edit.new_text, "\n\ntest");
+    }
+}
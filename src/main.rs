@@ -1,18 +1,20 @@
+mod config_command;
 mod configs;
+mod encoding;
+mod indent;
+mod list_providers;
 mod lsp;
 mod providers;
+mod secrets;
 mod server;
 mod state;
 
 use clap::{Parser, Subcommand};
-use configs::LlmConfig;
+use config_command::ConfigCommand;
 use inquire::{error::InquireError, Select};
-use providers::{
-    codeium_auth,
-    llm_api::{LlmClientApi, LlmState},
-};
+use providers::{GenerateOptions, Selection};
 use server::LlmLanguageServer;
-use tracing::warn;
+use toml::Table;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
@@ -21,56 +23,55 @@ async fn main() {
     match cli.command {
         Some(command) => {
             match command {
-                Commands::Server { provider } => {
-                    let llm_config = match LlmConfig::get_configs(&provider) {
-                        Ok(configs) => configs,
-                        Err(_) => {
-                            return;
-                        }
-                    };
-                    let llm_client = match provider.as_str() {
-                        "codeium" => {
-                            let api_key = match llm_config.get("API_KEY") {
-                                Some(k) => k,
-                                None => {
-                                    warn!("API_KEY not found in config");
-                                    return;
-                                }
-                            };
-                            let session_id = match llm_config.get("SESSION_ID") {
-                                Some(s) => s,
-                                None => {
-                                    warn!("SESSION_ID not found in config");
-                                    return;
-                                }
-                            };
-                            LlmState::new(api_key, session_id)
-                        }
-                        "ollama" | "openai" | "copilot" => {
-                            println!("{provider} is not supported yet");
-                            return;
-                        }
-                        _ => {
-                            println!("Invalid provider: {provider}");
-                            return;
+                Commands::Server { provider, profile } => {
+                    let selection = Selection { provider, profile };
+                    let llm_client = match selection.build(Table::new()) {
+                        Ok(llm_client) => llm_client,
+                        Err(error) => {
+                            // tracing is not set up yet and stdout belongs to the client
+                            eprintln!("llm-lsp: {error}");
+                            std::process::exit(1);
                         }
                     };
                     // run lsp-llm server
-                    LlmLanguageServer::run(llm_client).await;
+                    LlmLanguageServer::run(selection, llm_client).await;
                 }
-                Commands::GenerateConfig => {
-                    let providers: Vec<&str> = vec!["codeium"];
-                    let selected_provider: Result<&str, InquireError> =
-                        Select::new("Please select provider to generate config.", providers)
-                            .prompt();
+                Commands::Config { command } => {
+                    if let Err(error) = command.run().await {
+                        eprintln!("{error}");
+                        std::process::exit(1);
+                    }
+                }
+                Commands::ListProviders { json } => {
+                    if let Err(error) = list_providers::run(json) {
+                        eprintln!("{error}");
+                        std::process::exit(1);
+                    }
+                }
+                Commands::GenerateConfig(options) => {
+                    let selected_provider: Result<&str, InquireError> = match &options.provider {
+                        Some(provider) => Ok(provider),
+                        None if options.interactive() => Select::new(
+                            "Please select provider to generate config.",
+                            providers::provider_names(),
+                        )
+                        .prompt(),
+                        None => {
+                            eprintln!("Pass --provider to generate config without prompts.");
+                            std::process::exit(1);
+                        }
+                    };
 
-                    match selected_provider {
-                        Ok(provider) => match provider {
-                            "codeium" => codeium_auth::generate_api_key().await,
-                            "ollama" | "openai" | "copilot" => println!("{provider} is not supported yet"),
-                            _ => println!("Please specify a valid provider. To check valid providers run `llm-lsp list-providers`"),
+                    let generated = match selected_provider {
+                        Ok(provider) => match providers::get_provider(provider) {
+                            Some(llm_provider) => llm_provider.generate_config(options.clone()).await,
+                            None => Err("Please specify a valid provider. To check valid providers run `llm-lsp list-providers`".to_owned()),
                         },
-                        Err(error) =>println!("There was an error, please try again: {error}"),
+                        Err(error) => Err(format!("There was an error, please try again: {error}")),
+                    };
+                    if let Err(error) = generated {
+                        eprintln!("{error}");
+                        std::process::exit(1);
                     }
                 }
             }
@@ -96,10 +97,25 @@ enum Commands {
     #[command(arg_required_else_help = true)]
     Server {
         /// Name of the provider config
-        #[arg(short, long)]
-        provider: String,
+        #[arg(short, long, required_unless_present = "profile")]
+        provider: Option<String>,
+        /// Name of a `[profiles.<name>]` config, which also picks the provider
+        #[arg(long, conflicts_with = "provider")]
+        profile: Option<String>,
     },
     /// Run this command before running `llm-lsp server` command
     /// Generate auth token & save config in .config/llm-lsp/default-config.toml
-    GenerateConfig,
+    GenerateConfig(GenerateOptions),
+    /// List the providers `llm-lsp server --provider` accepts, whether they
+    /// are configured and what they support
+    ListProviders {
+        /// Print JSON for scripts
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect, edit or check the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
 }
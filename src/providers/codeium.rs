@@ -1,25 +1,129 @@
 use super::{
-    codeium_types,
-    llm_api::{CompletionRequest, LlmClientApi, LlmState},
-};
-use async_lsp::{
-    lsp_types::{
-        CompletionItem, CompletionItemKind, CompletionResponse, CompletionTextEdit, Position,
-        Range, TextEdit,
+    codeium_auth, codeium_types,
+    llm_api::{
+        ChatRequest, ChatResponse, ChatRole, ClientInfo, CompletionRequest, LlmClientApi,
+        OtherDocument, Suggestion,
     },
-    ResponseError,
 };
+use crate::{
+    configs::{from_str_or_value, validate_url},
+    secrets::SecretSources,
+};
+use async_lsp::{ErrorCode, ResponseError};
 use futures::future::BoxFuture;
 use reqwest::{
     header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONNECTION, CONTENT_TYPE},
     StatusCode,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{fmt::Display, ops::Range, str::FromStr};
 use tracing::{info, warn};
+use uuid::Uuid;
+
+pub const DEFAULT_API_URL: &str = "https://web-backend.codeium.com";
+const DEFAULT_OTHER_DOCUMENTS_BUDGET: usize = 32 * 1024;
+
+/// The `[codeium]` table, written by `llm-lsp generate-config`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct CodeiumConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Language server API, e.g. `https://codeium.example.com/_route/api_server`
+    /// for an enterprise deployment.
+    pub api_url: String,
+    /// Where `generate-config` signs in.
+    pub portal_url: String,
+    pub register_url: String,
+    /// Whether accepted completions are reported back to codeium.
+    #[serde(deserialize_with = "from_str_or_value")]
+    pub telemetry: bool,
+    /// Bytes of other open documents sent along with each completion.
+    #[serde(deserialize_with = "from_str_or_value")]
+    pub other_documents_budget: usize,
+    /// Reported instead of the editor's name, for editors codeium does not know.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ide_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ide_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension_version: Option<String>,
+    #[serde(flatten)]
+    pub secret_sources: SecretSources,
+}
+
+impl Default for CodeiumConfig {
+    fn default() -> Self {
+        CodeiumConfig {
+            api_key: None,
+            session_id: None,
+            api_url: DEFAULT_API_URL.to_owned(),
+            portal_url: codeium_auth::DEFAULT_PORTAL_URL.to_owned(),
+            register_url: codeium_auth::DEFAULT_REGISTER_URL.to_owned(),
+            telemetry: true,
+            other_documents_budget: DEFAULT_OTHER_DOCUMENTS_BUDGET,
+            ide_name: None,
+            ide_version: None,
+            extension_version: None,
+            secret_sources: SecretSources::default(),
+        }
+    }
+}
 
-impl LlmClientApi for LlmState {
-    fn new(api_key: &str, session_id: &str) -> LlmState {
-        let auth_url = "https://web-backend.codeium.com/exa.language_server_pb.LanguageServerService/GetCompletions".to_owned();
+impl CodeiumConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.secret_sources.check()?;
+        if self.api_key.as_deref().unwrap_or_default().is_empty() {
+            return Err("API_KEY is missing, run `llm-lsp generate-config`".to_owned());
+        }
+        if self.session_id.as_deref().unwrap_or_default().is_empty() {
+            return Err("SESSION_ID is missing, run `llm-lsp generate-config`".to_owned());
+        }
+        validate_url("API_URL", &self.api_url)?;
+        validate_url("PORTAL_URL", &self.portal_url)?;
+        validate_url("REGISTER_URL", &self.register_url)
+    }
+}
+
+pub struct CodeiumState {
+    pub auth_url: String,
+    pub chat_url: String,
+    pub accept_url: String,
+    pub api_key: String,
+    pub session_id: String,
+    pub telemetry: bool,
+    pub other_documents_budget: usize,
+    pub ide_name: Option<String>,
+    pub ide_version: Option<String>,
+    pub extension_version: Option<String>,
+    pub client_info: ClientInfo,
+    pub client: reqwest::Client,
+}
+
+impl CodeiumState {
+    pub fn new(config: CodeiumConfig) -> Result<CodeiumState, String> {
+        config.validate()?;
+        let CodeiumConfig {
+            api_key,
+            session_id,
+            api_url,
+            telemetry,
+            other_documents_budget,
+            ide_name,
+            ide_version,
+            extension_version,
+            ..
+        } = config;
+        let (api_key, session_id) = (api_key.unwrap_or_default(), session_id.unwrap_or_default());
+        let service_url = format!(
+            "{}/exa.language_server_pb.LanguageServerService",
+            api_url.trim_end_matches('/')
+        );
+        let auth_url = format!("{service_url}/GetCompletions");
+        let chat_url = format!("{service_url}/GetChatMessage");
+        let accept_url = format!("{service_url}/AcceptCompletion");
         let mut headers = HeaderMap::with_capacity(4);
         headers.insert(
             ACCEPT_ENCODING,
@@ -28,71 +132,221 @@ impl LlmClientApi for LlmState {
         headers.insert(
             AUTHORIZATION,
             format!("Basic {}-{}", api_key, session_id)
-                .as_str()
                 .parse()
-                .unwrap(),
+                .map_err(|_| "Invalid API_KEY or SESSION_ID in codeium config".to_owned())?,
         );
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
         let client = reqwest::Client::builder()
-            .default_headers(headers.clone())
+            .default_headers(headers)
             .build()
-            .unwrap();
-        LlmState {
+            .map_err(|error| format!("Client Error: {error}"))?;
+        Ok(CodeiumState {
             auth_url,
-            api_key: api_key.to_owned(),
-            session_id: session_id.to_owned(),
+            chat_url,
+            accept_url,
+            api_key,
+            session_id,
+            telemetry,
+            other_documents_budget,
+            ide_name,
+            ide_version,
+            extension_version,
+            client_info: ClientInfo {
+                name: "web".to_owned(),
+                version: "unknown".to_owned(),
+            },
             client,
+        })
+    }
+
+    // The editor name needs to be known by codeium, see `IDE_NAME`
+    // The extensionVersion needs to a recent one, so codeium accepts it
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ide_name: self
+                .ide_name
+                .clone()
+                .unwrap_or_else(|| self.client_info.name.clone()),
+            ide_version: self
+                .ide_version
+                .clone()
+                .unwrap_or_else(|| self.client_info.version.clone()),
+            extension_version: self
+                .extension_version
+                .clone()
+                .unwrap_or_else(|| "0.1.0".to_owned()),
+            extension_name: "llm-lsp".to_owned(),
+            api_key: self.api_key.clone(),
+            session_id: self.session_id.clone(),
+        }
+    }
+
+    /// Takes documents in order of relevance, skipping those that no longer
+    /// fit into the budget.
+    fn other_documents(&self, others: Vec<OtherDocument>) -> Vec<Document> {
+        let mut budget = self.other_documents_budget;
+        let mut documents = vec![];
+        for other in others {
+            if budget == 0 {
+                break;
+            }
+            let len = other.text.len_bytes();
+            if len > budget {
+                continue;
+            }
+            budget -= len;
+            let text = other.text.to_string();
+            let line_ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+            documents.push(Document {
+                language: codeium_types::LANGUAGE_MAP
+                    .get(&other.language_id)
+                    .unwrap_or(&0usize)
+                    .to_owned(),
+                editor_language: other.language_id,
+                cursor_offset: 0,
+                line_ending: line_ending.to_owned(),
+                absolute_path: other.filepath,
+                relative_path: other.relative_path,
+                text,
+            });
         }
+        documents
     }
+}
 
-    fn chat(&self) {}
+impl LlmClientApi for CodeiumState {
+    fn set_client_info(&mut self, client_info: &ClientInfo) {
+        self.client_info = client_info.clone();
+    }
+
+    fn chat(
+        &self,
+        request: ChatRequest,
+    ) -> BoxFuture<'static, Result<ChatResponse, ResponseError>> {
+        let conversation_id = Uuid::new_v4().to_string();
+        let mut system_prompt_override = None;
+        let mut chat_messages = Vec::with_capacity(request.messages.len());
+        for message in request.to_messages() {
+            let source = match message.role {
+                ChatRole::System => {
+                    system_prompt_override = Some(message.content);
+                    continue;
+                }
+                ChatRole::User => "CHAT_MESSAGE_SOURCE_USER",
+                ChatRole::Assistant => "CHAT_MESSAGE_SOURCE_SYSTEM",
+            };
+            chat_messages.push(CodeiumChatMessage {
+                message_id: Uuid::new_v4().to_string(),
+                source: source.to_owned(),
+                conversation_id: conversation_id.clone(),
+                intent: CodeiumChatIntent {
+                    generic: CodeiumChatText {
+                        text: message.content,
+                    },
+                },
+            });
+        }
+        let request_body = CodeiumChatRequest {
+            metadata: self.metadata(),
+            chat_messages,
+            system_prompt_override,
+        };
+        // GetChatMessage is a server-streaming Connect RPC, so both directions
+        // use enveloped JSON messages
+        let send = serde_json::to_vec(&request_body).map(|body| {
+            self.client
+                .post(self.chat_url.to_owned())
+                .header(CONTENT_TYPE, "application/connect+json")
+                .header("Connect-Protocol-Version", "1")
+                .body(connect_envelope(0, &body))
+                .send()
+        });
+        Box::pin(async move {
+            let send = send.map_err(|error| {
+                ResponseError::new(ErrorCode::INTERNAL_ERROR, format!("Json Error: {error}"))
+            })?;
+            let response = send.await.map_err(|error| {
+                ResponseError::new(ErrorCode::REQUEST_FAILED, format!("Post Error: {error}"))
+            })?;
+            let status = response.status();
+            if status != StatusCode::OK {
+                return Err(ResponseError::new(
+                    ErrorCode::REQUEST_FAILED,
+                    format!("http error: {status}"),
+                ));
+            }
+            let body = response.bytes().await.map_err(|error| {
+                ResponseError::new(ErrorCode::REQUEST_FAILED, format!("Body Error: {error}"))
+            })?;
+            let mut content = String::new();
+            for (flags, message) in connect_messages(&body) {
+                if flags & CONNECT_END_STREAM != 0 {
+                    if let Ok(CodeiumEndStream { error: Some(error) }) =
+                        serde_json::from_slice::<CodeiumEndStream>(message)
+                    {
+                        return Err(ResponseError::new(
+                            ErrorCode::REQUEST_FAILED,
+                            format!("{}: {}", error.code, error.message),
+                        ));
+                    }
+                    continue;
+                }
+                match serde_json::from_slice::<CodeiumChatResponse>(message) {
+                    // every message carries the whole answer generated so far
+                    Ok(chat_response) => {
+                        if let Some(action) = chat_response.chat_message.action {
+                            content = action.generic.text;
+                        }
+                    }
+                    Err(error) => warn!("JsonOk Error: {:?}", error),
+                }
+            }
+            Ok(ChatResponse { content })
+        })
+    }
+
+    fn uses_other_documents(&self) -> bool {
+        self.other_documents_budget > 0
+    }
 
     fn completion(
         &self,
         request: CompletionRequest,
-    ) -> BoxFuture<'static, Result<Option<CompletionResponse>, ResponseError>> {
+    ) -> BoxFuture<'static, Result<Vec<Suggestion>, ResponseError>> {
         let language = codeium_types::LANGUAGE_MAP
             .get(&request.language_id)
             .unwrap_or(&0usize)
             .to_owned();
-        let mut cursor_offset = 0usize;
-        let lines_len = request
-            .contents
-            .split("\n")
-            .map(|line| line.len())
-            .collect::<Vec<usize>>();
-        for i in 0..request.position_line {
-            if let Some(&len) = lines_len.get(i as usize) {
-                cursor_offset += len + 1;
-            }
-        }
-        cursor_offset += request.position_char as usize;
-        // The editor name needs to be known by codeium
-        // The extensionVersion needs to a recent one, so codeium accepts it
+        // codeium counts offsets in UTF-8 bytes, like `request.cursor_offset`
+        let cursor_offset = request.cursor_offset;
+        let line_ending = if request.contents.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        // without a range from codeium, suggestions replace the cursor line up to the cursor
+        let line_start = request.contents[..cursor_offset]
+            .rfind('\n')
+            .map_or(0, |idx| idx + 1);
+        let contents_len = request.contents.len();
+        let telemetry = self.telemetry;
         let request_body = CodeiumRequest {
-            metadata: Metadata {
-                ide_name: "web".to_owned(),
-                ide_version: "unknown".to_owned(),
-                extension_version: "0.1.0".to_owned(),
-                extension_name: "llm-lsp".to_owned(),
-                api_key: self.api_key.clone(),
-                session_id: self.session_id.clone(),
-            },
+            metadata: self.metadata(),
             document: Document {
                 editor_language: request.language_id,
                 language,
                 cursor_offset,
-                line_ending: "\n".to_owned(),
-                absolute_path: request.filepath.clone(),
-                relative_path: request.filepath.clone(),
+                line_ending: line_ending.to_owned(),
+                absolute_path: request.filepath,
+                relative_path: request.relative_path,
                 text: request.contents,
             },
             editor_options: EditorOptions {
-                tab_size: 2,
-                insert_spaces: true,
+                tab_size: request.tab_size,
+                insert_spaces: request.insert_spaces,
             },
-            other_documents: vec![],
+            other_documents: self.other_documents(request.other_documents),
         };
         let send = self
             .client
@@ -103,84 +357,88 @@ impl LlmClientApi for LlmState {
             match send.await {
                 Ok(response) => {
                     let status = response.status();
-                    let completion_response = match status {
+                    match status {
                         StatusCode::OK => match response.json::<CodeiumResponseOk>().await {
-                            Ok(resp_ok) => {
-                                if let Some(ref completion_items) = resp_ok.completion_items {
-                                    let mut items = Vec::with_capacity(completion_items.len());
-                                    let range = Range {
-                                        start: Position {
-                                            line: request.position_line,
-                                            character: 0,
-                                        },
-                                        end: Position {
-                                            line: request.position_line,
-                                            character: request.position_char,
-                                        },
-                                    };
-                                    for (idx, item) in completion_items.iter().enumerate() {
-                                        if idx == request.suggestions {
-                                            break;
-                                        }
-                                        let new_text = item.completion.text.to_owned();
-                                        items.push(CompletionItem {
-                                            label: item.completion.text.trim().to_owned(),
-                                            kind: Some(CompletionItemKind::TEXT),
-                                            text_edit: Some(CompletionTextEdit::Edit(TextEdit {
-                                                range,
-                                                new_text,
-                                            })),
-                                            ..Default::default()
-                                        });
+                            Ok(resp_ok) => Ok(resp_ok
+                                .completion_items
+                                .unwrap_or_default()
+                                .into_iter()
+                                .take(request.suggestions)
+                                .map(|item| {
+                                    let mut suggestion = item
+                                        .into_suggestion(line_start..cursor_offset, contents_len);
+                                    if !telemetry {
+                                        suggestion.id = None;
                                     }
-                                    items
-                                } else {
-                                    vec![]
-                                }
-                            }
+                                    suggestion
+                                })
+                                .collect()),
                             Err(error) => {
                                 warn!("JsonOk Error: {:?}", error);
-                                vec![CompletionItem::new_simple(
-                                    format!("{:?}", error),
-                                    "JsonOk Err".to_owned(),
-                                )]
+                                Err(ResponseError::new(
+                                    ErrorCode::REQUEST_FAILED,
+                                    format!("JsonOk Err: {:?}", error),
+                                ))
                             }
                         },
                         StatusCode::BAD_REQUEST => {
                             match response.json::<CodeiumResponseErr>().await {
                                 Ok(resp_err) => {
                                     info!("ResponseErr Value: {:?}", resp_err);
-                                    vec![CompletionItem::new_simple(
-                                        resp_err.code,
-                                        resp_err.message,
-                                    )]
+                                    Err(ResponseError::new(
+                                        ErrorCode::REQUEST_FAILED,
+                                        format!("{}: {}", resp_err.code, resp_err.message),
+                                    ))
                                 }
                                 Err(error) => {
                                     warn!("JsonErr Error: {:?}", error);
-                                    vec![CompletionItem::new_simple(
-                                        format!("{:?}", error),
-                                        "JsonErr Err".to_owned(),
-                                    )]
+                                    Err(ResponseError::new(
+                                        ErrorCode::REQUEST_FAILED,
+                                        format!("JsonErr Err: {:?}", error),
+                                    ))
                                 }
                             }
                         }
                         _ => {
                             info!("http error: {}", status);
-                            vec![CompletionItem::new_simple(
-                                "".to_owned(),
-                                "Json Ok Error".to_owned(),
-                            )]
+                            Err(ResponseError::new(
+                                ErrorCode::REQUEST_FAILED,
+                                format!("http error: {status}"),
+                            ))
                         }
-                    };
-                    Ok(Some(CompletionResponse::Array(completion_response)))
+                    }
                 }
                 Err(error) => {
                     info!("response error: {}", error);
-                    Ok(None)
+                    Ok(vec![])
                 }
             }
         })
     }
+
+    fn accept_completion(&self, id: String) -> BoxFuture<'static, Result<(), ResponseError>> {
+        let send = self
+            .client
+            .post(self.accept_url.to_owned())
+            .json(&CodeiumAcceptRequest {
+                metadata: self.metadata(),
+                completion_id: id,
+            })
+            .send();
+        Box::pin(async move {
+            let response = send.await.map_err(|error| {
+                ResponseError::new(ErrorCode::REQUEST_FAILED, format!("Post Error: {error}"))
+            })?;
+            let status = response.status();
+            if status != StatusCode::OK {
+                return Err(ResponseError::new(
+                    ErrorCode::REQUEST_FAILED,
+                    format!("http error: {status}"),
+                ));
+            }
+            Ok(())
+        })
+    }
 }
 
 #[derive(Serialize)]
@@ -188,7 +446,7 @@ struct CodeiumRequest {
     metadata: Metadata,
     document: Document,
     editor_options: EditorOptions,
-    other_documents: Vec<String>,
+    other_documents: Vec<Document>,
 }
 
 #[derive(Serialize)]
@@ -215,10 +473,17 @@ struct Document {
 
 #[derive(Serialize)]
 struct EditorOptions {
-    tab_size: usize, //2
+    tab_size: u32,
     insert_spaces: bool,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeiumAcceptRequest {
+    metadata: Metadata,
+    completion_id: String,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct CodeiumResponseOk {
@@ -229,16 +494,225 @@ struct CodeiumResponseOk {
 #[serde(rename_all = "camelCase")]
 struct CodeiumCompletionItems {
     completion: CodeiumCompletion,
+    /// Part of the document that `completion.text` replaces.
+    range: Option<CodeiumRange>,
+    /// Text to re-insert after the completion, e.g. closing brackets that
+    /// were right of the cursor.
+    suffix: Option<CodeiumSuffix>,
+}
+
+impl CodeiumCompletionItems {
+    /// Turns the item into an edit of the document, which is `contents_len`
+    /// bytes long. `fallback` is replaced when codeium sends no range.
+    fn into_suggestion(self, fallback: Range<usize>, contents_len: usize) -> Suggestion {
+        let id = Some(self.completion.completion_id).filter(|id| !id.is_empty());
+        let range = match self.range {
+            Some(range) => {
+                let start = range.start_offset.min(contents_len);
+                start..range.end_offset.clamp(start, contents_len)
+            }
+            None => fallback,
+        };
+        let mut text = self.completion.text;
+        if let Some(suffix) = self.suffix {
+            text.push_str(&suffix.text);
+        }
+        Suggestion { text, range, id }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct CodeiumCompletion {
+    #[serde(default)]
+    completion_id: String,
     text: String,
 }
 
+/// Byte offsets into the document sent with the request.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CodeiumRange {
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    start_offset: usize,
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    end_offset: usize,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CodeiumSuffix {
+    #[serde(default)]
+    text: String,
+}
+
+/// Protobuf's JSON mapping sends 64-bit integers as strings.
+fn deserialize_int64<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + TryFrom<u64>,
+    T::Err: Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Int64 {
+        Number(u64),
+        String(String),
+    }
+    match Int64::deserialize(deserializer)? {
+        Int64::Number(number) => T::try_from(number)
+            .map_err(|_| de::Error::custom(format!("integer out of range: {number}"))),
+        Int64::String(string) => string.parse().map_err(de::Error::custom),
+    }
+}
+
+const CONNECT_END_STREAM: u8 = 0b10;
+
+/// Frames `message` as a Connect streaming envelope: flags, big-endian length, payload.
+fn connect_envelope(flags: u8, message: &[u8]) -> Vec<u8> {
+    let mut envelope = Vec::with_capacity(message.len() + 5);
+    envelope.push(flags);
+    envelope.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    envelope.extend_from_slice(message);
+    envelope
+}
+
+/// Splits a Connect streaming body into `(flags, payload)` pairs.
+fn connect_messages(mut body: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut messages = vec![];
+    while body.len() >= 5 {
+        let flags = body[0];
+        let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        let Some(message) = body.get(5..5 + len) else {
+            break;
+        };
+        messages.push((flags, message));
+        body = &body[5 + len..];
+    }
+    messages
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeiumChatRequest {
+    metadata: Metadata,
+    chat_messages: Vec<CodeiumChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_prompt_override: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeiumChatMessage {
+    message_id: String,
+    source: String, // "CHAT_MESSAGE_SOURCE_USER"
+    conversation_id: String,
+    intent: CodeiumChatIntent,
+}
+
+#[derive(Serialize)]
+struct CodeiumChatIntent {
+    generic: CodeiumChatText,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CodeiumChatText {
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CodeiumChatResponse {
+    chat_message: CodeiumChatResponseMessage,
+}
+
+#[derive(Deserialize, Debug)]
+struct CodeiumChatResponseMessage {
+    action: Option<CodeiumChatAction>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CodeiumChatAction {
+    generic: CodeiumChatText,
+}
+
+#[derive(Deserialize, Debug)]
+struct CodeiumEndStream {
+    error: Option<CodeiumResponseErr>,
+}
+
 #[derive(Deserialize, Debug)]
 struct CodeiumResponseErr {
     code: String,
     message: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(json: &str) -> Vec<CodeiumCompletionItems> {
+        serde_json::from_str::<CodeiumResponseOk>(json)
+            .unwrap()
+            .completion_items
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn completion_with_range_and_suffix() {
+        // offsets arrive as strings or numbers
+        let items = items(
+            r#"{"completionItems": [{
+                "completion": {"completionId": "c1", "text": "    println!(\"hi\");"},
+                "range": {"startOffset": "12", "endOffset": 16},
+                "suffix": {"text": "\n}", "deltaCursorOffset": "-2"}
+            }]}"#,
+        );
+        let suggestion = items
+            .into_iter()
+            .next()
+            .unwrap()
+            .into_suggestion(12..16, 40);
+        assert_eq!(suggestion.range, 12..16);
+        assert_eq!(suggestion.text, "    println!(\"hi\");\n}");
+        assert_eq!(suggestion.id.as_deref(), Some("c1"));
+    }
+
+    #[test]
+    fn completion_range_clamped_to_document() {
+        let items = items(
+            r#"{"completionItems": [
+                {"completion": {"text": "a"}, "range": {"startOffset": "5", "endOffset": "99"}},
+                {"completion": {"text": "b"}, "range": {"startOffset": "99", "endOffset": "3"}},
+                {"completion": {"text": "c"}, "range": {"endOffset": "4"}}
+            ]}"#,
+        );
+        let ranges = items
+            .into_iter()
+            .map(|item| item.into_suggestion(0..0, 10).range)
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![5..10, 10..10, 0..4]);
+    }
+
+    #[test]
+    fn completion_without_range() {
+        assert!(items("{}").is_empty());
+        let items =
+            items(r#"{"completionItems": [{"completion": {"completionId": "", "text": "x"}}]}"#);
+        let suggestion = items.into_iter().next().unwrap().into_suggestion(3..7, 10);
+        assert_eq!(suggestion.range, 3..7);
+        assert_eq!(suggestion.text, "x");
+        assert_eq!(suggestion.id, None);
+    }
+
+    #[test]
+    fn int64_as_string_or_number() {
+        let range: CodeiumRange =
+            serde_json::from_str(r#"{"startOffset": "18446744073709551615", "endOffset": 7}"#)
+                .unwrap();
+        assert_eq!(range.start_offset, usize::MAX);
+        assert_eq!(range.end_offset, 7);
+        assert!(serde_json::from_str::<CodeiumRange>(r#"{"startOffset": "-1"}"#).is_err());
+        assert!(serde_json::from_str::<CodeiumRange>(r#"{"startOffset": 1.5}"#).is_err());
+    }
+}
@@ -1,46 +1,62 @@
+use super::{codeium::CodeiumConfig, parse_section, GenerateOptions};
 use crate::configs::LlmConfig;
 use inquire::Editor;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use toml::{Table, Value};
 use uuid::Uuid;
 
+pub const DEFAULT_PORTAL_URL: &str = "https://www.codeium.com";
+pub const DEFAULT_REGISTER_URL: &str = "https://api.codeium.com/register_user/";
+
 #[derive(Serialize, Deserialize)]
 struct RegisterResponse {
     name: String,
     api_key: String,
 }
 
-pub async fn generate_api_key() {
+/// Signs in through the codeium portal and stores the API key, or stores
+/// the key given with `--api-key`. `PORTAL_URL` and `REGISTER_URL` already
+/// in the codeium config select a self-hosted deployment, and
+/// `API_KEY_FILE`/`SESSION_ID_FILE` keep the keys out of it.
+pub async fn generate_api_key(options: GenerateOptions) -> Result<(), String> {
+    let llm_config = LlmConfig::load()?;
+    let config: CodeiumConfig = parse_section(options.current(&llm_config, "codeium")?)?;
     let session_id = Uuid::new_v4().to_string();
-    let auth_url = format!("https://www.codeium.com/profile?response_type=token&redirect_uri=vim-show-auth-token&state={}&scope=openid%20profile%20email&redirect_parameters_type=query", session_id);
-
-    let token = Editor::new("Authentication Token:")
-        .with_help_message(format!("Visit the following URL: {}", auth_url).as_str())
-        .prompt();
-
-    match token {
-        Ok(auth_token) => {
-            let auth_token = auth_token.trim().to_owned();
-            match register(auth_token).await {
-                Ok(api_key) => {
-                    let mut config_map = HashMap::new();
-                    config_map.insert("API_KEY".to_owned(), api_key);
-                    config_map.insert("SESSION_ID".to_owned(), session_id);
-                    _ = LlmConfig::generate_config("codeium".to_owned(), config_map);
-                }
-                Err(error) => println!("Error registering: {}", error),
-            }
+    let api_key = match options.api_key.clone() {
+        Some(api_key) => api_key,
+        None => {
+            let portal_url = config.portal_url.trim_end_matches('/');
+            let auth_url = format!("{}/profile?response_type=token&redirect_uri=vim-show-auth-token&state={}&scope=openid%20profile%20email&redirect_parameters_type=query", portal_url, session_id);
+            let auth_token = options
+                .value(options.auth_token.clone(), || {
+                    Editor::new("Authentication Token:")
+                        .with_help_message(
+                            format!("Visit the following URL: {}", auth_url).as_str(),
+                        )
+                        .prompt()
+                })?
+                .ok_or("Pass --auth-token or --api-key to configure codeium without prompts")?;
+            register(&config.register_url, auth_token.trim().to_owned())
+                .await
+                .map_err(|error| format!("Error registering: {}", error))?
         }
-        Err(error) => println!("Input Error: {error}"),
-    }
+    };
+    let mut settings = Table::new();
+    settings.insert(
+        "API_KEY".to_owned(),
+        Value::String(api_key.trim().to_owned()),
+    );
+    settings.insert("SESSION_ID".to_owned(), Value::String(session_id));
+    options.store("codeium", settings)
 }
 
-async fn register(auth_token: String) -> Result<String, String> {
+async fn register(register_url: &str, auth_token: String) -> Result<String, String> {
     let mut payload = HashMap::new();
     payload.insert("firebase_id_token", auth_token.as_str());
     let req_client = reqwest::Client::new();
     let api_key = req_client
-        .post("https://api.codeium.com/register_user/")
+        .post(register_url)
         .json(&payload)
         .send()
         .await
@@ -1,35 +1,167 @@
-use async_lsp::{lsp_types::CompletionResponse, ResponseError};
+use async_lsp::{ErrorCode, ResponseError};
 use futures::future::BoxFuture;
-use reqwest::Client;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
 
 pub struct CompletionRequest {
     pub contents: String,
     pub filepath: String,
+    /// `filepath` relative to its workspace folder, or `filepath` itself
+    /// outside of any workspace.
+    pub relative_path: String,
     pub language_id: String,
-    pub position_line: u32,
-    pub position_char: u32,
+    /// Cursor as a UTF-8 byte offset into `contents`, whatever position
+    /// encoding the client negotiated.
+    pub cursor_offset: usize,
     pub suggestions: usize,
-    #[allow(unused)]
-    pub client_name: String,
-    #[allow(unused)]
-    pub client_version: String,
+    /// Indentation the document uses, so suggestions can match it.
+    pub tab_size: u32,
+    pub insert_spaces: bool,
+    /// Other open documents, most relevant first, left empty unless the
+    /// provider `uses_other_documents`.
+    pub other_documents: Vec<OtherDocument>,
 }
 
-pub struct LlmState {
-    pub auth_url: String,
-    pub api_key: String,
-    pub session_id: String,
-    pub client: Client,
+/// The editor, as reported in `initialize`.
+#[derive(Clone, Default)]
+pub struct ClientInfo {
+    pub name: String,
+    pub version: String,
 }
 
-pub trait LlmClientApi {
-    fn new(api_key: &str, session_id: &str) -> Self;
+pub struct OtherDocument {
+    pub filepath: String,
+    pub relative_path: String,
+    pub language_id: String,
+    pub text: Rope,
+}
+
+/// A completion proposed by a provider, independent of how the editor shows it.
+pub struct Suggestion {
+    /// Text replacing `range`.
+    pub text: String,
+    /// Byte range of `CompletionRequest::contents` replaced by `text`.
+    pub range: Range<usize>,
+    /// Provider id passed to `LlmClientApi::accept_completion` once the
+    /// suggestion is accepted.
+    pub id: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn user(content: impl Into<String>) -> Self {
+        ChatMessage {
+            role: ChatRole::User,
+            content: content.into(),
+        }
+    }
+}
+
+/// Code the conversation is about, e.g. the selection of a code action.
+pub struct CodeContext {
+    /// Workspace-relative path of the file.
+    pub filepath: String,
+    pub language_id: String,
+    pub code: String,
+}
+
+pub struct ChatRequest {
+    pub system_prompt: Option<String>,
+    /// Conversation so far, oldest first, ending with the user's question.
+    pub messages: Vec<ChatMessage>,
+    pub context: Option<CodeContext>,
+}
+
+impl ChatRequest {
+    /// Flattens the request into the role/content list chat APIs expect,
+    /// with the code context appended to the system prompt.
+    pub fn to_messages(&self) -> Vec<ChatMessage> {
+        let mut system_prompt = self.system_prompt.clone().unwrap_or_default();
+        if let Some(context) = &self.context {
+            if !system_prompt.is_empty() {
+                system_prompt.push_str("\n\n");
+            }
+            system_prompt.push_str(&format!(
+                "File: {} ({})\n```{}\n{}\n```",
+                context.filepath, context.language_id, context.language_id, context.code
+            ));
+        }
+        let mut messages = Vec::with_capacity(self.messages.len() + 1);
+        if !system_prompt.is_empty() {
+            messages.push(ChatMessage {
+                role: ChatRole::System,
+                content: system_prompt,
+            });
+        }
+        messages.extend(self.messages.iter().cloned());
+        messages
+    }
+}
+
+pub struct ChatResponse {
+    pub content: String,
+}
 
-    #[allow(unused)]
-    fn chat(&self);
+pub trait LlmClientApi: Send + Sync {
+    fn chat(&self, request: ChatRequest)
+        -> BoxFuture<'static, Result<ChatResponse, ResponseError>>;
+
+    /// Tells the provider which editor it serves, once known.
+    fn set_client_info(&mut self, _client_info: &ClientInfo) {}
+
+    /// Whether completions make use of `CompletionRequest::other_documents`,
+    /// which are only collected for providers that do.
+    fn uses_other_documents(&self) -> bool {
+        false
+    }
 
     fn completion(
         &self,
         completion_request: CompletionRequest,
-    ) -> BoxFuture<'static, Result<Option<CompletionResponse>, ResponseError>>;
+    ) -> BoxFuture<'static, Result<Vec<Suggestion>, ResponseError>>;
+
+    /// Reports that the suggestion with `id` was accepted. Providers without
+    /// acceptance feedback ignore it.
+    fn accept_completion(&self, _id: String) -> BoxFuture<'static, Result<(), ResponseError>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The error for an answer that stopped at the token limit, which must not
+/// be applied to the document as if it were complete.
+pub fn truncated_answer() -> ResponseError {
+    ResponseError::new(
+        ErrorCode::REQUEST_FAILED,
+        "The answer was cut off at the token limit",
+    )
+}
+
+/// Chat models tend to wrap code in a markdown fence despite being told not to.
+pub fn strip_code_fence(content: &str) -> String {
+    let trimmed = content.trim();
+    match trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+    {
+        // drop the language tag following the opening fence
+        Some(fenced) => match fenced.split_once('\n') {
+            Some((_, code)) => code.to_owned(),
+            None => fenced.to_owned(),
+        },
+        None => content.to_owned(),
+    }
 }
@@ -0,0 +1,65 @@
+//! A stand-in for a provider's HTTP API, answering with canned responses so
+//! the clients can be tested without a model.
+
+use super::llm_api::CompletionRequest;
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// Answers one request per connection with the next of `responses`, as
+/// `(status, JSON body)`. Returns the server's URL and the bodies of the
+/// requests it received.
+pub fn serve(responses: Vec<(u16, &'static str)>) -> (String, Receiver<Value>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (requests, received) = mpsc::channel();
+    thread::spawn(move || {
+        for (status, body) in responses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut request = vec![0; content_length];
+            reader.read_exact(&mut request).unwrap();
+            let _ = requests.send(serde_json::from_slice(&request).unwrap_or_default());
+            write!(
+                reader.into_inner(),
+                "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\n\
+                Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        }
+    });
+    (url, received)
+}
+
+/// A completion request with the cursor at `cursor_offset` of `contents`.
+pub fn completion_request(contents: &str, cursor_offset: usize) -> CompletionRequest {
+    CompletionRequest {
+        contents: contents.to_owned(),
+        filepath: "/project/src/main.rs".to_owned(),
+        relative_path: "src/main.rs".to_owned(),
+        language_id: "rust".to_owned(),
+        cursor_offset,
+        suggestions: 3,
+        tab_size: 4,
+        insert_spaces: true,
+        other_documents: vec![],
+    }
+}
@@ -2,4 +2,362 @@ pub mod codeium;
 pub mod codeium_auth;
 pub mod codeium_types;
 pub mod llm_api;
+#[cfg(test)]
+mod mock_server;
 pub mod ollama;
+pub mod openai;
+
+use crate::{
+    configs::{LlmConfig, Profile},
+    secrets,
+};
+use codeium::{CodeiumConfig, CodeiumState};
+use futures::future::BoxFuture;
+use inquire::InquireError;
+use llm_api::LlmClientApi;
+use ollama::{OllamaConfig, OllamaState};
+use openai::{OpenAiConfig, OpenAiEndpoint, OpenAiState};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{stdin, IsTerminal};
+use toml::{Table, Value};
+
+type BuildFn = fn(Table) -> Result<Box<dyn LlmClientApi>, String>;
+type ValidateFn = fn(Table) -> Result<(), String>;
+type TableFn = fn(&LlmConfig) -> Result<Table, String>;
+type SetTableFn = fn(&mut LlmConfig, Table) -> Result<(), String>;
+
+/// A backend that can be selected with `llm-lsp server --provider <name>`.
+pub struct Provider {
+    pub name: &'static str,
+    /// Keys that can instead be read from a `<KEY>_ENV`, `<KEY>_COMMAND`
+    /// or `<KEY>_FILE` source.
+    pub secrets: &'static [&'static str],
+    pub capabilities: Capabilities,
+    /// Reads and replaces the provider's table of `LlmConfig`.
+    table: TableFn,
+    set_table: SetTableFn,
+    build: BuildFn,
+    validate: ValidateFn,
+    check_keys: ValidateFn,
+    generate_config: fn(GenerateOptions) -> BoxFuture<'static, Result<(), String>>,
+}
+
+impl Provider {
+    pub fn table(&self, llm_config: &LlmConfig) -> Result<Table, String> {
+        (self.table)(llm_config)
+    }
+
+    pub fn set_table(&self, llm_config: &mut LlmConfig, section: Table) -> Result<(), String> {
+        (self.set_table)(llm_config, section)
+    }
+
+    /// Constructs the provider client from its `LlmConfig` section, with
+    /// any profile settings applied, reading its secrets from their sources.
+    pub fn build(&self, mut section: Table) -> Result<Box<dyn LlmClientApi>, String> {
+        secrets::resolve(&mut section, self.secrets)?;
+        (self.build)(section)
+    }
+
+    /// Checks the settings `build` would be given, without constructing the
+    /// client.
+    pub fn validate(&self, mut section: Table) -> Result<(), String> {
+        secrets::resolve(&mut section, self.secrets)?;
+        (self.validate)(section)
+    }
+
+    /// Whether `section` holds everything the provider needs, trusting
+    /// that secret sources deliver.
+    pub fn is_configured(&self, mut section: Table) -> bool {
+        secrets::assume(&mut section, self.secrets);
+        (self.validate)(section).is_ok()
+    }
+
+    /// Fails on keys in `section` that the provider's table does not
+    /// declare, other than the sources of its secrets.
+    pub fn check_keys(&self, mut section: Table) -> Result<(), String> {
+        secrets::assume(&mut section, self.secrets);
+        (self.check_keys)(section)
+    }
+
+    /// Asks for the provider's settings not given in `options` and stores them.
+    pub async fn generate_config(&self, options: GenerateOptions) -> Result<(), String> {
+        (self.generate_config)(options).await
+    }
+}
+
+/// What a provider supports, as listed by `llm-lsp list-providers`.
+#[derive(Clone, Copy, Serialize)]
+pub struct Capabilities {
+    /// Inline completions while typing.
+    pub completion: bool,
+    /// Chat requests, which the code action commands are sent as.
+    pub chat: bool,
+    /// Completions see the text after the cursor, not only before it.
+    pub fill_in_middle: bool,
+}
+
+impl Capabilities {
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.completion, "completion"),
+            (self.chat, "chat"),
+            (self.fill_in_middle, "fill-in-middle"),
+        ]
+        .into_iter()
+        .filter_map(|(supported, name)| supported.then_some(name))
+        .collect()
+    }
+}
+
+pub static PROVIDERS: &[Provider] = &[
+    Provider {
+        name: "codeium",
+        secrets: &["API_KEY", "SESSION_ID"],
+        capabilities: Capabilities {
+            completion: true,
+            chat: true,
+            fill_in_middle: true,
+        },
+        table: |llm_config| to_section(&llm_config.codeium),
+        set_table: |llm_config, section| {
+            llm_config.codeium = parse_section(section)?;
+            Ok(())
+        },
+        build: |section| {
+            let config: CodeiumConfig = parse_section(section)?;
+            Ok(Box::new(CodeiumState::new(config)?))
+        },
+        validate: |section| parse_section::<CodeiumConfig>(section)?.validate(),
+        check_keys: |section| {
+            parse_section::<CodeiumConfig>(section)?
+                .secret_sources
+                .check()
+        },
+        generate_config: |options| Box::pin(codeium_auth::generate_api_key(options)),
+    },
+    Provider {
+        name: "ollama",
+        secrets: &[],
+        capabilities: Capabilities {
+            completion: true,
+            chat: true,
+            fill_in_middle: true,
+        },
+        table: |llm_config| to_section(&llm_config.ollama),
+        set_table: |llm_config, section| {
+            llm_config.ollama = parse_section(section)?;
+            Ok(())
+        },
+        build: |section| {
+            let config: OllamaConfig = parse_section(section)?;
+            Ok(Box::new(OllamaState::new(config)?))
+        },
+        validate: |section| parse_section::<OllamaConfig>(section)?.validate(),
+        check_keys: |section| parse_section::<OllamaConfig>(section)?.unknown_keys.check(),
+        generate_config: |options| Box::pin(async move { ollama::generate_config(options) }),
+    },
+    Provider {
+        name: "openai",
+        secrets: &["API_KEY"],
+        capabilities: Capabilities {
+            completion: true,
+            chat: true,
+            // with `ENDPOINT = "completions"`
+            fill_in_middle: true,
+        },
+        table: |llm_config| to_section(&llm_config.openai),
+        set_table: |llm_config, section| {
+            llm_config.openai = parse_section(section)?;
+            Ok(())
+        },
+        build: |section| {
+            let config: OpenAiConfig = parse_section(section)?;
+            Ok(Box::new(OpenAiState::new(config)?))
+        },
+        validate: |section| parse_section::<OpenAiConfig>(section)?.validate(),
+        check_keys: |section| {
+            parse_section::<OpenAiConfig>(section)?
+                .secret_sources
+                .check()
+        },
+        generate_config: |options| Box::pin(async move { openai::generate_config(options) }),
+    },
+];
+
+fn to_section<T: Serialize>(config: &T) -> Result<Table, String> {
+    Table::try_from(config).map_err(|error| error.to_string())
+}
+
+/// Reads a provider's typed config; validation happens when the client is built.
+fn parse_section<T: DeserializeOwned>(section: Table) -> Result<T, String> {
+    T::deserialize(toml::Value::Table(section)).map_err(|error| error.to_string())
+}
+
+/// Settings given to `llm-lsp generate-config` on the command line. Only the
+/// missing ones are prompted for, and only when stdin is a terminal.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct GenerateOptions {
+    /// Provider to configure, instead of picking one from a list
+    #[arg(short, long)]
+    pub provider: Option<String>,
+    /// Store the settings as `[profiles.<name>]` instead of the provider's table
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Token from the Codeium sign-in page, exchanged for an API key
+    #[arg(long, conflicts_with = "api_key")]
+    pub auth_token: Option<String>,
+    /// API key for Codeium or an OpenAI-compatible server
+    #[arg(long)]
+    pub api_key: Option<String>,
+    /// Server URL for Ollama or an OpenAI-compatible server
+    #[arg(long)]
+    pub base_url: Option<String>,
+    /// Model for Ollama or an OpenAI-compatible server
+    #[arg(long)]
+    pub model: Option<String>,
+    /// API of an OpenAI-compatible server
+    #[arg(long, value_enum)]
+    pub endpoint: Option<OpenAiEndpoint>,
+    /// Fail instead of prompting for missing settings
+    #[arg(long)]
+    pub no_input: bool,
+}
+
+impl GenerateOptions {
+    pub fn interactive(&self) -> bool {
+        !self.no_input && stdin().is_terminal()
+    }
+
+    /// Returns `given`, otherwise asks with `prompt` when interactive.
+    pub fn value<T>(
+        &self,
+        given: Option<T>,
+        prompt: impl FnOnce() -> Result<T, InquireError>,
+    ) -> Result<Option<T>, String> {
+        match given {
+            Some(value) => Ok(Some(value)),
+            None if self.interactive() => prompt()
+                .map(Some)
+                .map_err(|error| format!("Input Error: {error}")),
+            None => Ok(None),
+        }
+    }
+
+    /// The settings `provider` currently has where `store` would write.
+    pub fn current(&self, llm_config: &LlmConfig, provider: &str) -> Result<Table, String> {
+        match &self.profile {
+            Some(profile) if llm_config.profiles.contains_key(profile) => llm_config
+                .get_profile(profile)
+                .map(|(_, settings)| settings),
+            _ => llm_config.get_configs(provider),
+        }
+    }
+
+    /// Writes `settings` into the provider's table or the profile, with
+    /// secrets going to their `*_FILE` when one is configured.
+    pub fn store(&self, provider: &str, mut settings: Table) -> Result<(), String> {
+        let llm_provider = get_provider(provider)
+            .ok_or_else(|| format!("Provider {provider} is not supported as of now!"))?;
+        llm_provider.check_keys(settings.clone())?;
+        let mut llm_config = LlmConfig::load()?;
+        let current = self.current(&llm_config, provider)?;
+        for secret in llm_provider.secrets {
+            let Some(Value::String(value)) = settings.remove(*secret) else {
+                continue;
+            };
+            if let Some(value) = secrets::store(&current, secret, value)? {
+                settings.insert(secret.to_string(), Value::String(value));
+            }
+        }
+        match &self.profile {
+            Some(name) => {
+                let profile = llm_config.profiles.entry(name.clone()).or_insert(Profile {
+                    provider: provider.to_owned(),
+                    settings: Table::new(),
+                });
+                if profile.provider != provider {
+                    return Err(format!(
+                        "Profile {name} uses provider {}, not {provider}",
+                        profile.provider
+                    ));
+                }
+                secrets::extend(&mut profile.settings, settings);
+                // a profile's settings are only checked against their types here
+                let (_, layered) = llm_config.get_profile(name)?;
+                LlmConfig::default().set_configs(provider, layered)?;
+            }
+            None => {
+                let mut section = llm_config.get_configs(provider)?;
+                section.extend(settings);
+                llm_config.set_configs(provider, section)?;
+            }
+        }
+        llm_config.store()
+    }
+}
+
+/// Provider settings the editor may override. Workspace settings come with
+/// the project, so they must not point the provider at another server or
+/// name where secrets are read from.
+pub const EDITOR_SETTINGS: &[&str] = &[
+    "MODEL",
+    "TEMPERATURE",
+    "NUM_PREDICT",
+    "MAX_TOKENS",
+    "ENDPOINT",
+];
+
+/// The provider settings `llm-lsp server` runs with: a provider's table, or
+/// a profile when one is given.
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    pub provider: Option<String>,
+    pub profile: Option<String>,
+}
+
+impl Selection {
+    /// Builds the client from the config file, with `overrides`, e.g.
+    /// settings sent by the editor, applied on top.
+    pub fn build(&self, overrides: Table) -> Result<Box<dyn LlmClientApi>, String> {
+        let llm_config = LlmConfig::load()?;
+        let (provider, mut section, table) = match (&self.provider, &self.profile) {
+            (_, Some(profile)) => {
+                let (provider, section) = llm_config.get_profile(profile)?;
+                (provider, section, format!("[profiles.{profile}]"))
+            }
+            (Some(provider), None) => {
+                let section = llm_config.get_configs(provider).unwrap_or_default();
+                (provider.clone(), section, format!("[{provider}]"))
+            }
+            (None, None) => return Err("Specify --provider or --profile".to_owned()),
+        };
+        if let Some(key) = overrides
+            .keys()
+            .find(|key| !EDITOR_SETTINGS.contains(&key.as_str()))
+        {
+            return Err(format!(
+                "Editor settings may only override {}, not {key}",
+                EDITOR_SETTINGS.join(", ")
+            ));
+        }
+        let mut source = LlmConfig::path();
+        if !overrides.is_empty() {
+            source.push_str(" with editor settings");
+        }
+        section.extend(overrides);
+        let llm_provider = get_provider(&provider).ok_or_else(|| {
+            format!("Invalid provider: {provider}. To check valid providers run `llm-lsp list-providers`")
+        })?;
+        llm_provider
+            .build(section)
+            .map_err(|error| format!("Invalid {table} config in {source}: {error}"))
+    }
+}
+
+pub fn get_provider(name: &str) -> Option<&'static Provider> {
+    PROVIDERS.iter().find(|provider| provider.name == name)
+}
+
+pub fn provider_names() -> Vec<&'static str> {
+    PROVIDERS.iter().map(|provider| provider.name).collect()
+}
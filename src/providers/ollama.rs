@@ -0,0 +1,393 @@
+use super::{
+    llm_api::{
+        truncated_answer, ChatMessage, ChatRequest, ChatResponse, CompletionRequest, LlmClientApi,
+        Suggestion,
+    },
+    GenerateOptions,
+};
+use crate::configs::{option_from_str_or_value, validate_url, LlmConfig, UnknownKeys};
+use async_lsp::{ErrorCode, ResponseError};
+use futures::{future::BoxFuture, Future};
+use inquire::Text;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+    Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+use tracing::{info, warn};
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+/// The `[ollama]` table.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct OllamaConfig {
+    pub base_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(
+        deserialize_with = "option_from_str_or_value",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub temperature: Option<f32>,
+    #[serde(
+        deserialize_with = "option_from_str_or_value",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub num_predict: Option<i32>,
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        OllamaConfig {
+            base_url: DEFAULT_BASE_URL.to_owned(),
+            model: None,
+            temperature: None,
+            num_predict: None,
+            unknown_keys: UnknownKeys::default(),
+        }
+    }
+}
+
+impl OllamaConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.unknown_keys.check()?;
+        validate_url("BASE_URL", &self.base_url)?;
+        if self.model.as_deref().unwrap_or_default().is_empty() {
+            return Err("MODEL is missing, e.g. `qwen2.5-coder:1.5b`".to_owned());
+        }
+        if self
+            .temperature
+            .is_some_and(|temperature| temperature < 0.0)
+        {
+            return Err("TEMPERATURE must not be negative".to_owned());
+        }
+        Ok(())
+    }
+}
+
+pub struct OllamaState {
+    pub base_url: String,
+    pub model: String,
+    pub options: OllamaOptions,
+    pub client: reqwest::Client,
+}
+
+impl OllamaState {
+    pub fn new(config: OllamaConfig) -> Result<OllamaState, String> {
+        config.validate()?;
+        let options = OllamaOptions {
+            temperature: config.temperature,
+            num_predict: config.num_predict,
+        };
+        let mut headers = HeaderMap::with_capacity(1);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|error| format!("Client Error: {error}"))?;
+        Ok(OllamaState {
+            base_url: config.base_url.trim_end_matches('/').to_owned(),
+            model: config.model.unwrap_or_default(),
+            options,
+            client,
+        })
+    }
+}
+
+impl OllamaState {
+    fn send_chat(
+        &self,
+        request: ChatRequest,
+    ) -> impl Future<Output = Result<Response, ResponseError>> {
+        let request_body = OllamaChatRequest {
+            model: self.model.clone(),
+            messages: request.to_messages(),
+            stream: false,
+            // `NUM_PREDICT` is sized for completions and would cut rewrites short
+            options: OllamaOptions {
+                num_predict: None,
+                ..self.options.clone()
+            },
+        };
+        let send = self
+            .client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&request_body)
+            .send();
+        async move {
+            let response = send.await.map_err(|error| {
+                ResponseError::new(ErrorCode::REQUEST_FAILED, format!("Post Error: {error}"))
+            })?;
+            let status = response.status();
+            if status != StatusCode::OK {
+                let message = match response.json::<OllamaResponseErr>().await {
+                    Ok(resp_err) => resp_err.error,
+                    Err(_) => status.to_string(),
+                };
+                return Err(ResponseError::new(ErrorCode::REQUEST_FAILED, message));
+            }
+            Ok(response)
+        }
+    }
+}
+
+impl LlmClientApi for OllamaState {
+    fn chat(
+        &self,
+        request: ChatRequest,
+    ) -> BoxFuture<'static, Result<ChatResponse, ResponseError>> {
+        let send = self.send_chat(request);
+        Box::pin(async move {
+            let resp_ok = send
+                .await?
+                .json::<OllamaChatResponseOk>()
+                .await
+                .map_err(|error| {
+                    ResponseError::new(ErrorCode::REQUEST_FAILED, format!("Json Error: {error}"))
+                })?;
+            if resp_ok.done_reason.as_deref() == Some("length") {
+                return Err(truncated_answer());
+            }
+            Ok(ChatResponse {
+                content: resp_ok.message.content,
+            })
+        })
+    }
+
+    fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> BoxFuture<'static, Result<Vec<Suggestion>, ResponseError>> {
+        // Ollama fills the gap between `prompt` and `suffix` when the model
+        // has a FIM template, so split the document at the cursor.
+        let (prefix, suffix) = request.contents.split_at(request.cursor_offset);
+        let request_body = OllamaRequest {
+            model: self.model.clone(),
+            prompt: prefix.to_owned(),
+            suffix: suffix.to_owned(),
+            stream: false,
+            options: self.options.clone(),
+        };
+        let send = self
+            .client
+            .post(format!("{}/api/generate", self.base_url))
+            .json(&request_body)
+            .send();
+        let cursor_offset = request.cursor_offset;
+        Box::pin(async move {
+            match send.await {
+                Ok(response) => {
+                    let status = response.status();
+                    match status {
+                        StatusCode::OK => match response.json::<OllamaResponseOk>().await {
+                            Ok(resp_ok) => {
+                                if resp_ok.response.trim().is_empty() {
+                                    Ok(vec![])
+                                } else {
+                                    Ok(vec![Suggestion {
+                                        text: resp_ok.response,
+                                        range: cursor_offset..cursor_offset,
+                                        id: None,
+                                    }])
+                                }
+                            }
+                            Err(error) => {
+                                warn!("JsonOk Error: {:?}", error);
+                                Err(ResponseError::new(
+                                    ErrorCode::REQUEST_FAILED,
+                                    format!("JsonOk Err: {:?}", error),
+                                ))
+                            }
+                        },
+                        _ => match response.json::<OllamaResponseErr>().await {
+                            Ok(resp_err) => {
+                                info!("ResponseErr Value: {:?}", resp_err);
+                                Err(ResponseError::new(
+                                    ErrorCode::REQUEST_FAILED,
+                                    format!("{status}: {}", resp_err.error),
+                                ))
+                            }
+                            Err(error) => {
+                                warn!("JsonErr Error: {:?}", error);
+                                Err(ResponseError::new(
+                                    ErrorCode::REQUEST_FAILED,
+                                    format!("JsonErr Err: {:?}", error),
+                                ))
+                            }
+                        },
+                    }
+                }
+                Err(error) => {
+                    info!("response error: {}", error);
+                    Ok(vec![])
+                }
+            }
+        })
+    }
+}
+
+pub fn generate_config(options: GenerateOptions) -> Result<(), String> {
+    let current = options.current(&LlmConfig::load()?, "ollama")?;
+    let base_url = options.value(options.base_url.clone(), || {
+        Text::new("Ollama base URL:")
+            .with_default(DEFAULT_BASE_URL)
+            .prompt()
+    })?;
+    let model = options.value(options.model.clone(), || {
+        Text::new("Model name:")
+            .with_help_message("A model pulled into ollama, e.g. `qwen2.5-coder:1.5b`")
+            .prompt()
+    })?;
+    if model.is_none() && !current.contains_key("MODEL") {
+        return Err("Pass --model to configure ollama without prompts".to_owned());
+    }
+
+    let mut settings = Table::new();
+    if let Some(base_url) = base_url {
+        settings.insert("BASE_URL".to_owned(), Value::from(base_url.trim()));
+    }
+    if let Some(model) = model {
+        settings.insert("MODEL".to_owned(), Value::from(model.trim()));
+    }
+    options.store("ollama", settings)
+}
+
+#[derive(Serialize)]
+struct OllamaRequest {
+    model: String,
+    prompt: String,
+    suffix: String,
+    stream: bool,
+    options: OllamaOptions,
+}
+
+#[derive(Serialize)]
+struct OllamaChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    options: OllamaOptions,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaResponseOk {
+    response: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaChatResponseOk {
+    message: ChatMessage,
+    /// Set on the last message, `length` when it hit the token limit.
+    done_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaResponseErr {
+    error: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::mock_server::{completion_request, serve};
+
+    fn client(base_url: String) -> OllamaState {
+        OllamaState::new(OllamaConfig {
+            base_url,
+            model: Some("qwen2.5-coder:1.5b".to_owned()),
+            num_predict: Some(128),
+            ..OllamaConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn chat_request() -> ChatRequest {
+        ChatRequest {
+            system_prompt: Some("Reply with code".to_owned()),
+            messages: vec![ChatMessage::user("Improve this code.")],
+            context: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn completion() {
+        let (url, requests) = serve(vec![
+            (200, r#"{"response": "println!(\"hi\");", "done": true}"#),
+            (200, r#"{"response": "  \n", "done": true}"#),
+        ]);
+        let ollama = client(url);
+        let suggestions = ollama
+            .completion(completion_request("fn main() {\n    \n}\n", 16))
+            .await
+            .unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].text, "println!(\"hi\");");
+        assert_eq!(suggestions[0].range, 16..16);
+        let request = requests.recv().unwrap();
+        assert_eq!(request["prompt"], "fn main() {\n    ");
+        assert_eq!(request["suffix"], "\n}\n");
+        assert_eq!(request["options"]["num_predict"], 128);
+
+        let suggestions = ollama
+            .completion(completion_request("fn main() {}", 12))
+            .await
+            .unwrap();
+        assert!(suggestions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn completion_error() {
+        let (url, _) = serve(vec![(404, r#"{"error": "model not found"}"#)]);
+        let error = client(url)
+            .completion(completion_request("fn", 2))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.message, "404 Not Found: model not found");
+    }
+
+    #[tokio::test]
+    async fn chat() {
+        let (url, requests) = serve(vec![(
+            200,
+            r#"{"message": {"role": "assistant", "content": "```rust\nfn a() {}\n```"},
+                "done": true, "done_reason": "stop"}"#,
+        )]);
+        let response = client(url).chat(chat_request()).await.unwrap();
+        assert_eq!(response.content, "```rust\nfn a() {}\n```");
+        let request = requests.recv().unwrap();
+        assert_eq!(request["stream"], false);
+        assert_eq!(request["messages"][0]["role"], "system");
+        // completions' token limit does not apply to chats
+        assert!(request["options"].get("num_predict").is_none());
+    }
+
+    #[tokio::test]
+    async fn chat_cut_off() {
+        let (url, _) = serve(vec![
+            (
+                200,
+                r#"{"message": {"role": "assistant", "content": "fn a() {"},
+                    "done": true, "done_reason": "length"}"#,
+            ),
+            (500, r#"{"error": "out of memory"}"#),
+        ]);
+        let ollama = client(url);
+        let error = ollama.chat(chat_request()).await.err().unwrap();
+        assert_eq!(error.message, truncated_answer().message);
+        let error = ollama.chat(chat_request()).await.err().unwrap();
+        assert_eq!(error.message, "out of memory");
+    }
+}
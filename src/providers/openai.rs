@@ -0,0 +1,530 @@
+use super::{
+    llm_api::{
+        strip_code_fence, truncated_answer, ChatMessage, ChatRequest, ChatResponse, ChatRole,
+        CompletionRequest, LlmClientApi, Suggestion,
+    },
+    GenerateOptions,
+};
+use crate::{
+    configs::{option_from_str_or_value, validate_url, LlmConfig},
+    secrets::SecretSources,
+};
+use async_lsp::{ErrorCode, ResponseError};
+use futures::{future::BoxFuture, Future};
+use inquire::{Password, PasswordDisplayMode, Select, Text};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use toml::{Table, Value};
+use tracing::{info, warn};
+
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+const COMPLETION_SYSTEM_PROMPT: &str = "You are a code completion engine. \
+The user sends a file with a <CURSOR> marker. \
+Reply with only the code to insert at the marker, \
+without explanations and without markdown code fences.";
+
+/// Which of the two OpenAI wire formats the server speaks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenAiEndpoint {
+    /// `/v1/chat/completions`, supported by every OpenAI-compatible server.
+    #[default]
+    Chat,
+    /// Legacy `/v1/completions` with `suffix`, used for fill-in-the-middle.
+    Completions,
+}
+
+impl Display for OpenAiEndpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenAiEndpoint::Chat => write!(f, "chat"),
+            OpenAiEndpoint::Completions => write!(f, "completions"),
+        }
+    }
+}
+
+/// The `[openai]` table.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct OpenAiConfig {
+    pub base_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub endpoint: OpenAiEndpoint,
+    /// Local servers such as llama.cpp or LM Studio usually run without a key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(
+        deserialize_with = "option_from_str_or_value",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_tokens: Option<u32>,
+    #[serde(
+        deserialize_with = "option_from_str_or_value",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub temperature: Option<f32>,
+    #[serde(flatten)]
+    pub secret_sources: SecretSources,
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        OpenAiConfig {
+            base_url: DEFAULT_BASE_URL.to_owned(),
+            model: None,
+            endpoint: OpenAiEndpoint::default(),
+            api_key: None,
+            max_tokens: None,
+            temperature: None,
+            secret_sources: SecretSources::default(),
+        }
+    }
+}
+
+impl OpenAiConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.secret_sources.check()?;
+        validate_url("BASE_URL", &self.base_url)?;
+        if self.model.as_deref().unwrap_or_default().is_empty() {
+            return Err("MODEL is missing".to_owned());
+        }
+        if self
+            .temperature
+            .is_some_and(|temperature| !(0.0..=2.0).contains(&temperature))
+        {
+            return Err("TEMPERATURE must be between 0 and 2".to_owned());
+        }
+        if self.max_tokens == Some(0) {
+            return Err("MAX_TOKENS must be positive".to_owned());
+        }
+        Ok(())
+    }
+}
+
+pub struct OpenAiState {
+    pub base_url: String,
+    pub model: String,
+    pub endpoint: OpenAiEndpoint,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub client: reqwest::Client,
+}
+
+impl OpenAiState {
+    pub fn new(config: OpenAiConfig) -> Result<OpenAiState, String> {
+        config.validate()?;
+        let mut headers = HeaderMap::with_capacity(2);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if let Some(api_key) = config.api_key.filter(|api_key| !api_key.is_empty()) {
+            headers.insert(
+                AUTHORIZATION,
+                format!("Bearer {api_key}")
+                    .parse()
+                    .map_err(|_| "Invalid API_KEY in openai config".to_owned())?,
+            );
+        }
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|error| format!("Client Error: {error}"))?;
+        Ok(OpenAiState {
+            base_url: config.base_url.trim_end_matches('/').to_owned(),
+            model: config.model.unwrap_or_default(),
+            endpoint: config.endpoint,
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+            client,
+        })
+    }
+}
+
+impl OpenAiState {
+    fn send_chat(
+        &self,
+        request: ChatRequest,
+    ) -> impl Future<Output = Result<Response, ResponseError>> {
+        let request_body = ChatCompletionRequest {
+            model: self.model.clone(),
+            messages: request.to_messages(),
+            max_tokens: None,
+            temperature: self.temperature,
+            stream: false,
+        };
+        let send = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&request_body)
+            .send();
+        async move {
+            let response = send.await.map_err(|error| {
+                ResponseError::new(ErrorCode::REQUEST_FAILED, format!("Post Error: {error}"))
+            })?;
+            let status = response.status();
+            if status != StatusCode::OK {
+                let message = match response.json::<OpenAiResponseErr>().await {
+                    Ok(resp_err) => resp_err.error.message,
+                    Err(_) => status.to_string(),
+                };
+                return Err(ResponseError::new(ErrorCode::REQUEST_FAILED, message));
+            }
+            Ok(response)
+        }
+    }
+}
+
+impl LlmClientApi for OpenAiState {
+    fn chat(
+        &self,
+        request: ChatRequest,
+    ) -> BoxFuture<'static, Result<ChatResponse, ResponseError>> {
+        let send = self.send_chat(request);
+        Box::pin(async move {
+            let resp_ok = send
+                .await?
+                .json::<OpenAiResponseOk>()
+                .await
+                .map_err(|error| {
+                    ResponseError::new(ErrorCode::REQUEST_FAILED, format!("Json Error: {error}"))
+                })?;
+            let choice = resp_ok.choices.into_iter().next();
+            if choice
+                .as_ref()
+                .and_then(|choice| choice.finish_reason.as_deref())
+                == Some("length")
+            {
+                return Err(truncated_answer());
+            }
+            choice
+                .and_then(|choice| choice.message)
+                .and_then(|message| message.content)
+                .map(|content| ChatResponse { content })
+                .ok_or_else(|| ResponseError::new(ErrorCode::REQUEST_FAILED, "Empty chat response"))
+        })
+    }
+
+    fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> BoxFuture<'static, Result<Vec<Suggestion>, ResponseError>> {
+        let (prefix, suffix) = request.contents.split_at(request.cursor_offset);
+        let send = match self.endpoint {
+            OpenAiEndpoint::Chat => {
+                let indentation = if request.insert_spaces {
+                    format!("{} spaces", request.tab_size)
+                } else {
+                    "tabs".to_owned()
+                };
+                let request_body = ChatCompletionRequest {
+                    model: self.model.clone(),
+                    messages: vec![
+                        ChatMessage {
+                            role: ChatRole::System,
+                            content: COMPLETION_SYSTEM_PROMPT.to_owned(),
+                        },
+                        ChatMessage {
+                            role: ChatRole::User,
+                            content: format!(
+                                "File: {} ({}, indented with {})\n\n{prefix}<CURSOR>{suffix}",
+                                request.relative_path, request.language_id, indentation
+                            ),
+                        },
+                    ],
+                    max_tokens: self.max_tokens,
+                    temperature: self.temperature,
+                    stream: false,
+                };
+                self.client
+                    .post(format!("{}/chat/completions", self.base_url))
+                    .json(&request_body)
+                    .send()
+            }
+            OpenAiEndpoint::Completions => {
+                let request_body = TextCompletionRequest {
+                    model: self.model.clone(),
+                    prompt: prefix.to_owned(),
+                    suffix: suffix.to_owned(),
+                    max_tokens: self.max_tokens,
+                    temperature: self.temperature,
+                };
+                self.client
+                    .post(format!("{}/completions", self.base_url))
+                    .json(&request_body)
+                    .send()
+            }
+        };
+        let endpoint = self.endpoint;
+        let cursor_offset = request.cursor_offset;
+        Box::pin(async move {
+            match send.await {
+                Ok(response) => {
+                    let status = response.status();
+                    match status {
+                        StatusCode::OK => match response.json::<OpenAiResponseOk>().await {
+                            Ok(resp_ok) => Ok(resp_ok
+                                .choices
+                                .into_iter()
+                                .filter_map(|choice| match endpoint {
+                                    OpenAiEndpoint::Chat => choice
+                                        .message
+                                        .and_then(|message| message.content)
+                                        .map(|content| strip_code_fence(&content)),
+                                    OpenAiEndpoint::Completions => choice.text,
+                                })
+                                .filter(|text| !text.trim().is_empty())
+                                .take(request.suggestions)
+                                .map(|text| Suggestion {
+                                    text,
+                                    range: cursor_offset..cursor_offset,
+                                    id: None,
+                                })
+                                .collect()),
+                            Err(error) => {
+                                warn!("JsonOk Error: {:?}", error);
+                                Err(ResponseError::new(
+                                    ErrorCode::REQUEST_FAILED,
+                                    format!("JsonOk Err: {:?}", error),
+                                ))
+                            }
+                        },
+                        _ => match response.json::<OpenAiResponseErr>().await {
+                            Ok(resp_err) => {
+                                info!("ResponseErr Value: {:?}", resp_err);
+                                Err(ResponseError::new(
+                                    ErrorCode::REQUEST_FAILED,
+                                    format!("{status}: {}", resp_err.error.message),
+                                ))
+                            }
+                            Err(error) => {
+                                warn!("JsonErr Error: {:?}", error);
+                                Err(ResponseError::new(
+                                    ErrorCode::REQUEST_FAILED,
+                                    format!("JsonErr Err: {:?}", error),
+                                ))
+                            }
+                        },
+                    }
+                }
+                Err(error) => {
+                    info!("response error: {}", error);
+                    Ok(vec![])
+                }
+            }
+        })
+    }
+}
+
+pub fn generate_config(options: GenerateOptions) -> Result<(), String> {
+    let current = options.current(&LlmConfig::load()?, "openai")?;
+    let base_url = options.value(options.base_url.clone(), || {
+        Text::new("Base URL:")
+            .with_default(DEFAULT_BASE_URL)
+            .with_help_message("e.g. http://localhost:8080/v1 for llama.cpp server")
+            .prompt()
+    })?;
+    let model = options.value(options.model.clone(), || Text::new("Model name:").prompt())?;
+    if model.is_none() && !current.contains_key("MODEL") {
+        return Err("Pass --model to configure openai without prompts".to_owned());
+    }
+    let endpoint = options.value(options.endpoint, || {
+        Select::new(
+            "Endpoint:",
+            vec![OpenAiEndpoint::Chat, OpenAiEndpoint::Completions],
+        )
+        .prompt()
+    })?;
+    let api_key = options.value(options.api_key.clone(), || {
+        Password::new("API key:")
+            .with_display_mode(PasswordDisplayMode::Masked)
+            .without_confirmation()
+            .with_help_message("Leave empty if the server does not require one")
+            .prompt()
+    })?;
+
+    let mut settings = Table::new();
+    if let Some(base_url) = base_url {
+        settings.insert("BASE_URL".to_owned(), Value::from(base_url.trim()));
+    }
+    if let Some(model) = model {
+        settings.insert("MODEL".to_owned(), Value::from(model.trim()));
+    }
+    if let Some(endpoint) = endpoint {
+        settings.insert("ENDPOINT".to_owned(), Value::from(endpoint.to_string()));
+    }
+    if let Some(api_key) = api_key.filter(|api_key| !api_key.trim().is_empty()) {
+        settings.insert("API_KEY".to_owned(), Value::from(api_key.trim()));
+    }
+    options.store("openai", settings)
+}
+
+#[derive(Serialize)]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    stream: bool,
+}
+
+#[derive(Serialize)]
+struct TextCompletionRequest {
+    model: String,
+    prompt: String,
+    suffix: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiResponseOk {
+    choices: Vec<OpenAiChoice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiChoice {
+    /// Set by `/v1/completions`
+    text: Option<String>,
+    /// Set by `/v1/chat/completions`
+    message: Option<OpenAiMessage>,
+    /// `length` when the answer hit the token limit.
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiResponseErr {
+    error: OpenAiError,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiError {
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::mock_server::{completion_request, serve};
+
+    fn client(base_url: String, endpoint: OpenAiEndpoint) -> OpenAiState {
+        OpenAiState::new(OpenAiConfig {
+            base_url,
+            model: Some("qwen2.5-coder".to_owned()),
+            endpoint,
+            max_tokens: Some(64),
+            ..OpenAiConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn chat_request() -> ChatRequest {
+        ChatRequest {
+            system_prompt: None,
+            messages: vec![ChatMessage::user("Improve this code.")],
+            context: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_completion_strips_fence() {
+        let (url, requests) = serve(vec![(
+            200,
+            r#"{"choices": [
+                {"message": {"role": "assistant", "content": "```rust\nlet x = 1;\n```"},
+                 "finish_reason": "stop"},
+                {"message": {"role": "assistant", "content": " "}, "finish_reason": "stop"}
+            ]}"#,
+        )]);
+        let suggestions = client(url, OpenAiEndpoint::Chat)
+            .completion(completion_request("fn main() {\n    \n}\n", 16))
+            .await
+            .unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].text, "let x = 1;\n");
+        assert_eq!(suggestions[0].range, 16..16);
+        let request = requests.recv().unwrap();
+        assert_eq!(request["max_tokens"], 64);
+        assert!(request["messages"][1]["content"]
+            .as_str()
+            .unwrap()
+            .ends_with("fn main() {\n    <CURSOR>\n}\n"));
+    }
+
+    #[tokio::test]
+    async fn text_completion() {
+        let (url, requests) = serve(vec![
+            (
+                200,
+                r#"{"choices": [{"text": "let x = 1;", "finish_reason": "stop"}]}"#,
+            ),
+            (200, r#"{"choices": []}"#),
+        ]);
+        let openai = client(url, OpenAiEndpoint::Completions);
+        let suggestions = openai
+            .completion(completion_request("fn main() {\n    \n}\n", 16))
+            .await
+            .unwrap();
+        assert_eq!(suggestions[0].text, "let x = 1;");
+        let request = requests.recv().unwrap();
+        assert_eq!(request["prompt"], "fn main() {\n    ");
+        assert_eq!(request["suffix"], "\n}\n");
+        let suggestions = openai.completion(completion_request("", 0)).await.unwrap();
+        assert!(suggestions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn completion_error() {
+        let (url, _) = serve(vec![(
+            401,
+            r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error"}}"#,
+        )]);
+        let error = client(url, OpenAiEndpoint::Completions)
+            .completion(completion_request("fn", 2))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.message,
+            "401 Unauthorized: Incorrect API key provided"
+        );
+    }
+
+    #[tokio::test]
+    async fn chat() {
+        let (url, requests) = serve(vec![
+            (
+                200,
+                r#"{"choices": [{"message": {"role": "assistant", "content": "fn a() {}"},
+                    "finish_reason": "stop"}]}"#,
+            ),
+            (
+                200,
+                r#"{"choices": [{"message": {"role": "assistant", "content": "fn a() {"},
+                    "finish_reason": "length"}]}"#,
+            ),
+            (429, r#"{"error": {"message": "Rate limit reached"}}"#),
+        ]);
+        let openai = client(url, OpenAiEndpoint::Chat);
+        let response = openai.chat(chat_request()).await.unwrap();
+        assert_eq!(response.content, "fn a() {}");
+        // completions' token limit does not apply to chats
+        assert!(requests.recv().unwrap().get("max_tokens").is_none());
+        let error = openai.chat(chat_request()).await.err().unwrap();
+        assert_eq!(error.message, truncated_answer().message);
+        let error = openai.chat(chat_request()).await.err().unwrap();
+        assert_eq!(error.message, "Rate limit reached");
+    }
+}
@@ -0,0 +1,350 @@
+use crate::configs::UnknownKeys;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use toml::{Table, Value};
+
+/// Where a secret such as `API_KEY` can come from instead of the config
+/// file itself, named by the suffix of its key, e.g. `API_KEY_COMMAND`.
+#[derive(Clone, Copy)]
+enum Source {
+    /// An environment variable.
+    Env,
+    /// The output of a shell command, e.g. `pass show codeium/api-key`.
+    Command,
+    /// A file only its owner can read.
+    File,
+}
+
+impl Source {
+    const ALL: [Source; 3] = [Source::Env, Source::Command, Source::File];
+
+    /// The secret `key` is a source of, e.g. `API_KEY` for `API_KEY_FILE`.
+    fn secret_of(key: &str) -> Option<&str> {
+        Source::ALL
+            .into_iter()
+            .find_map(|source| key.strip_suffix(&source.key("")))
+    }
+
+    fn key(self, secret: &str) -> String {
+        match self {
+            Source::Env => format!("{secret}_ENV"),
+            Source::Command => format!("{secret}_COMMAND"),
+            Source::File => format!("{secret}_FILE"),
+        }
+    }
+
+    fn read(self, location: &str) -> Result<String, String> {
+        match self {
+            Source::Env => env::var(location)
+                .map(|value| value.trim().to_owned())
+                .map_err(|_| format!("environment variable {location} is not set")),
+            Source::Command => run_command(location),
+            Source::File => read_file(&expand_home(location)),
+        }
+    }
+}
+
+/// The keys of a provider table that name where its secrets are read from,
+/// such as `API_KEY_COMMAND`, along with any unknown keys. `resolve` takes
+/// the sources out before the table is read, so any left are sources of
+/// settings that are not secrets.
+#[derive(Clone, Default)]
+pub struct SecretSources {
+    sources: Table,
+    unknown_keys: UnknownKeys,
+}
+
+impl SecretSources {
+    pub fn check(&self) -> Result<(), String> {
+        self.unknown_keys.check()?;
+        match self.sources.keys().next() {
+            Some(key) => Err(format!(
+                "{} is not a secret, it cannot be read from {key}",
+                Source::secret_of(key).unwrap_or_default()
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Serialize for SecretSources {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut keys = self.sources.clone();
+        keys.extend(self.unknown_keys.0.clone());
+        keys.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretSources {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (sources, unknown) = Table::deserialize(deserializer)?
+            .into_iter()
+            .partition(|(key, _)| Source::secret_of(key).is_some());
+        Ok(SecretSources {
+            sources,
+            unknown_keys: UnknownKeys(unknown),
+        })
+    }
+}
+
+/// Replaces the sources of each of `secrets` in `section` with the secret
+/// itself. Secrets are only read here, when a provider is built, so a
+/// password manager is not asked for keys of providers that are not used.
+pub fn resolve(section: &mut Table, secrets: &[&str]) -> Result<(), String> {
+    for secret in secrets {
+        let mut sources = Source::ALL
+            .into_iter()
+            .filter_map(|source| Some((source, section.remove(&source.key(secret))?)));
+        let Some((source, location)) = sources.next() else {
+            continue;
+        };
+        if sources.next().is_some() || section.contains_key(*secret) {
+            return Err(format!("Set only one of {}", all_keys(secret).join(", ")));
+        }
+        let key = source.key(secret);
+        let Value::String(location) = location else {
+            return Err(format!("{key} must be a string"));
+        };
+        let value = source
+            .read(&location)
+            .map_err(|error| format!("Could not read {key}: {error}"))?;
+        section.insert(secret.to_string(), Value::String(value));
+    }
+    Ok(())
+}
+
+/// Like `resolve`, but stands in a placeholder for each secret that has a
+/// source instead of reading it, to check a config without side effects.
+pub fn assume(section: &mut Table, secrets: &[&str]) {
+    for secret in secrets {
+        for source in Source::ALL {
+            if section.remove(&source.key(secret)).is_some() {
+                section.insert(secret.to_string(), Value::from("<secret>"));
+            }
+        }
+    }
+}
+
+/// Stores a newly generated secret: into its `*_FILE` if one is configured,
+/// otherwise into the config file, which is what `Ok(Some(_))` asks for.
+pub fn store(section: &Table, secret: &str, value: String) -> Result<Option<String>, String> {
+    if let Some(location) = section
+        .get(&Source::File.key(secret))
+        .and_then(Value::as_str)
+    {
+        write_file(&expand_home(location), &value)?;
+        return Ok(None);
+    }
+    for source in [Source::Env, Source::Command] {
+        let key = source.key(secret);
+        if section.contains_key(&key) {
+            return Err(format!(
+                "{secret} is read from {key}, store the new value there: {value}"
+            ));
+        }
+    }
+    Ok(Some(value))
+}
+
+/// Like `Table::extend`, except that a secret or one of its sources in
+/// `layer` replaces the secret and all of its sources in `settings`, so that
+/// a profile can read a key from elsewhere than the provider's table.
+pub fn extend(settings: &mut Table, layer: Table) {
+    for key in layer.keys() {
+        let secret = Source::secret_of(key).unwrap_or(key);
+        for key in all_keys(secret) {
+            settings.remove(&key);
+        }
+    }
+    settings.extend(layer);
+}
+
+fn all_keys(secret: &str) -> Vec<String> {
+    let mut keys = vec![secret.to_owned()];
+    keys.extend(Source::ALL.map(|source| source.key(secret)));
+    keys
+}
+
+fn run_command(command: &str) -> Result<String, String> {
+    #[cfg(unix)]
+    let output = Command::new("sh").arg("-c").arg(command).output();
+    #[cfg(not(unix))]
+    let output = Command::new("cmd").arg("/C").arg(command).output();
+    let output = output.map_err(|error| format!("`{command}` failed to start: {error}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut error = format!("`{command}` failed with {}", output.status);
+        if !stderr.trim().is_empty() {
+            error = format!("{error}: {}", stderr.trim());
+        }
+        return Err(error);
+    }
+    // `pass` and similar tools print the secret on the first line
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().next().unwrap_or_default().trim().to_owned())
+}
+
+fn read_file(path: &Path) -> Result<String, String> {
+    let metadata = fs::metadata(path).map_err(|error| format!("{}: {error}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(format!(
+                "{} is accessible by other users (mode {:o}), run `chmod 600 {}`",
+                path.display(),
+                mode & 0o777,
+                path.display()
+            ));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+    let contents =
+        fs::read_to_string(path).map_err(|error| format!("{}: {error}", path.display()))?;
+    Ok(contents.trim().to_owned())
+}
+
+fn write_file(path: &Path, value: &str) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|error| format!("{}: {error}", path.display()))?;
+    #[cfg(unix)]
+    {
+        // `mode` only applies to new files
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(|error| format!("{}: {error}", path.display()))?;
+    }
+    writeln!(file, "{value}").map_err(|error| format!("{}: {error}", path.display()))
+}
+
+/// Expands a leading `~/` to the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(rest))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(contents: &str) -> Table {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn conflicting_sources() {
+        let mut section = table(
+            r#"
+            API_KEY_ENV = "LLM_LSP_TEST_KEY"
+            API_KEY_FILE = "~/.codeium-key"
+            "#,
+        );
+        assert_eq!(
+            resolve(&mut section, &["API_KEY"]).unwrap_err(),
+            "Set only one of API_KEY, API_KEY_ENV, API_KEY_COMMAND, API_KEY_FILE"
+        );
+        let mut section = table(
+            r#"
+            API_KEY = "key"
+            API_KEY_COMMAND = "pass show codeium/api-key"
+            "#,
+        );
+        assert!(resolve(&mut section, &["API_KEY"]).is_err());
+    }
+
+    #[test]
+    fn trims_sources() {
+        env::set_var("LLM_LSP_TEST_TRIMMED_KEY", " key\n");
+        let mut section = table(
+            r#"
+            API_KEY_ENV = "LLM_LSP_TEST_TRIMMED_KEY"
+            SESSION_ID_COMMAND = "echo '  session  '; echo second line"
+            "#,
+        );
+        resolve(&mut section, &["API_KEY", "SESSION_ID"]).unwrap();
+        assert_eq!(
+            section,
+            table("API_KEY = \"key\"\nSESSION_ID = \"session\"")
+        );
+    }
+
+    #[test]
+    fn profile_replaces_provider_secret() {
+        let mut settings = table(
+            r#"
+            API_KEY = "provider key"
+            MODEL = "provider model"
+            "#,
+        );
+        extend(
+            &mut settings,
+            table(r#"API_KEY_COMMAND = "pass show work/api-key""#),
+        );
+        assert_eq!(
+            settings,
+            table("MODEL = \"provider model\"\nAPI_KEY_COMMAND = \"pass show work/api-key\"")
+        );
+
+        // and the other way around
+        let mut settings = table(r#"API_KEY_FILE = "~/.api-key""#);
+        extend(&mut settings, table(r#"API_KEY = "profile key""#));
+        assert_eq!(settings, table(r#"API_KEY = "profile key""#));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_readable_by_others() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = env::temp_dir().join(format!("llm-lsp-test-key-{}", std::process::id()));
+        write_file(&path, "key").unwrap();
+        let mut section = Table::from_iter([(
+            "API_KEY_FILE".to_owned(),
+            Value::from(path.display().to_string()),
+        )]);
+        resolve(&mut section.clone(), &["API_KEY"]).unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let error = resolve(&mut section, &["API_KEY"]).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(
+            error.contains("is accessible by other users (mode 644)"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn sources_of_settings() {
+        let sources: SecretSources = toml::from_str(
+            r#"
+            MODEL_COMMAND = "echo model"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            sources.check().unwrap_err(),
+            "MODEL is not a secret, it cannot be read from MODEL_COMMAND"
+        );
+        let sources: SecretSources = toml::from_str("TEMPRATURE = 0.2").unwrap();
+        assert_eq!(sources.check().unwrap_err(), "Unknown key TEMPRATURE");
+    }
+}
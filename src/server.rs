@@ -1,56 +1,98 @@
 use crate::{
     configs::{Command, LspConfig},
-    providers::llm_api::LlmClientApi,
+    providers::{llm_api::LlmClientApi, Selection},
     state::LanguageState,
 };
 use async_lsp::{
-    client_monitor::ClientProcessMonitorLayer, concurrency::ConcurrencyLayer,
-    panic::CatchUnwindLayer, router::Router, server::LifecycleLayer, tracing::TracingLayer,
+    client_monitor::ClientProcessMonitorLayer,
+    concurrency::ConcurrencyLayer,
+    lsp_types::{request::InlineCompletionRequest, Url},
+    panic::CatchUnwindLayer,
+    router::Router,
+    server::LifecycleLayer,
+    tracing::TracingLayer,
     ClientSocket,
 };
-use std::{ops::ControlFlow, time::Duration};
+use std::{collections::HashMap, num::NonZeroUsize, ops::ControlFlow, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
 use tower::ServiceBuilder;
 use tracing::Level;
 
-pub struct LlmLanguageServer<'a, T>
-where
-    T: LlmClientApi,
-{
-    #[allow(unused)]
+const MAX_CONCURRENT_REQUESTS: NonZeroUsize = NonZeroUsize::new(64).unwrap();
+
+pub struct LlmLanguageServer<'a> {
     pub client: ClientSocket,
     pub commands: Vec<Command<'a>>,
     pub trigger_characters: Vec<&'a str>,
+    pub completion_debounce: Duration,
+    /// Latest completion request per document and kind; a newer one cancels
+    /// it. Clients may send both kinds for the same keystroke.
+    pub pending_completions: HashMap<(Url, CompletionKind), CancellationToken>,
     pub state: LanguageState,
-    pub llm_client: T,
+    /// Provider settings from the command line, which editor settings are
+    /// layered over when the client is rebuilt.
+    pub selection: Selection,
+    /// Shared with completion requests, which only use it once the debounce
+    /// window has passed.
+    pub llm_client: Arc<dyn LlmClientApi>,
+    /// Turned off per project through the editor's settings.
+    pub completions_enabled: bool,
+    /// Counts editor settings, so that only the client built from the
+    /// latest ones is applied.
+    pub settings_generation: u64,
 }
 
 pub struct TickEvent;
 
-impl<'a, T: LlmClientApi + 'static> LlmLanguageServer<'a, T> {
+/// The request a completion answers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompletionKind {
+    /// `textDocument/completion`, shown in the completion menu.
+    Menu,
+    /// `textDocument/inlineCompletion`, shown as ghost text.
+    Inline,
+}
+
+/// The provider client rebuilt from editor settings.
+pub struct ReloadEvent {
+    pub generation: u64,
+    pub result: Result<Box<dyn LlmClientApi>, String>,
+    pub completions_enabled: bool,
+}
+
+impl<'a> LlmLanguageServer<'a> {
     pub fn new_router(
         client: ClientSocket,
         lsp_config: LspConfig<'a>,
-        llm_client: T,
+        selection: Selection,
+        llm_client: Box<dyn LlmClientApi>,
     ) -> Router<Self> {
         let mut router = Router::from_language_server(Self {
             client,
             commands: lsp_config.commands,
             trigger_characters: lsp_config.trigger_characters,
+            completion_debounce: lsp_config.completion_debounce,
+            pending_completions: HashMap::new(),
             state: LanguageState::new(),
-            llm_client,
+            selection,
+            llm_client: Arc::from(llm_client),
+            completions_enabled: true,
+            settings_generation: 0,
         });
+        router.request::<InlineCompletionRequest, _>(Self::inline_completion);
         router.event(Self::on_tick);
+        router.event(Self::on_reload);
         router
     }
 
     fn on_tick(&mut self, _: TickEvent) -> ControlFlow<async_lsp::Result<()>> {
+        // finished and superseded requests leave their tokens cancelled
+        self.pending_completions
+            .retain(|_, token| !token.is_cancelled());
         ControlFlow::Continue(())
     }
 
-    pub async fn run(llm_client: T)
-    where
-        T: LlmClientApi,
-    {
+    pub async fn run(selection: Selection, llm_client: Box<dyn LlmClientApi>) {
         let lsp_config = LspConfig::init();
 
         let (server, _) = async_lsp::MainLoop::new_server(|client| {
@@ -71,10 +113,13 @@ impl<'a, T: LlmClientApi + 'static> LlmLanguageServer<'a, T> {
                 .layer(TracingLayer::default())
                 .layer(LifecycleLayer::default())
                 .layer(CatchUnwindLayer::default())
-                .layer(ConcurrencyLayer::default())
+                // the main loop stops polling running requests while it waits for a
+                // free slot, so the default limit of one per CPU would hang on as
+                // few as two overlapping completions
+                .layer(ConcurrencyLayer::new(MAX_CONCURRENT_REQUESTS))
                 .layer(ClientProcessMonitorLayer::new(client.clone()))
                 .service(LlmLanguageServer::new_router(
-                    client, lsp_config, llm_client,
+                    client, lsp_config, selection, llm_client,
                 ))
         });
 
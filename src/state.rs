@@ -1,66 +1,410 @@
-use async_lsp::lsp_types::Url;
+use crate::{
+    encoding::PositionEncoding,
+    indent::{EditorConfigIndent, IndentStyle},
+    providers::llm_api::{ClientInfo, OtherDocument},
+};
+use async_lsp::lsp_types::{
+    Range, TextDocumentContentChangeEvent, Url, WorkspaceFolder, WorkspaceFoldersChangeEvent,
+};
+use ropey::Rope;
 use std::{
     collections::HashMap,
+    path::Path,
     sync::{Arc, RwLock},
+    time::Instant,
 };
 use tracing::info;
 
+/// Everything known about the editor session. Cheap to clone, so request
+/// futures can take it along; clones share the open documents.
+#[derive(Clone)]
 pub struct LanguageState {
-    documents: Arc<RwLock<HashMap<Url, String>>>,
-    language_ids: Arc<RwLock<HashMap<Url, String>>>,
+    documents: Documents,
     pub client_info: ClientInfo,
+    pub position_encoding: PositionEncoding,
+    workspace_folders: Arc<Vec<WorkspaceFolder>>,
+}
+
+/// An open text document, kept from `didOpen` until `didClose`.
+pub struct Document {
+    pub text: Rope,
+    pub language_id: String,
+    pub version: i32,
+    pub last_modified: Instant,
+    /// Indentation settings from `.editorconfig`, read when the document opens.
+    pub editorconfig: EditorConfigIndent,
+    /// Indentation for suggestions, worked out on open and again whenever
+    /// lines are added or removed rather than on every completion.
+    pub indent: IndentStyle,
 }
 
-#[derive(Default)]
-pub struct ClientInfo {
-    pub name: String,
-    pub version: String,
+/// Shared handle to the open documents, cheap to clone into request futures.
+#[derive(Clone, Default)]
+pub struct Documents(Arc<RwLock<HashMap<Url, Document>>>);
+
+impl Documents {
+    pub fn get_version(&self, uri: &Url) -> Option<i32> {
+        self.0
+            .read()
+            .expect("poison")
+            .get(uri)
+            .map(|doc| doc.version)
+    }
 }
 
 impl LanguageState {
     pub fn new() -> Self {
         LanguageState {
             documents: Default::default(),
-            language_ids: Default::default(),
             client_info: Default::default(),
+            position_encoding: Default::default(),
+            workspace_folders: Default::default(),
         }
     }
 
-    pub fn get_contents(&self, uri: &Url) -> String {
+    pub fn documents(&self) -> Documents {
+        self.documents.clone()
+    }
+
+    /// Returns a snapshot of the document, which is cheap to clone.
+    pub fn get_text(&self, uri: &Url) -> Rope {
         self.documents
+            .0
             .read()
             .expect("poison")
             .get(uri)
-            .map(|s| s.to_owned())
+            .map(|doc| doc.text.clone())
             .unwrap_or_default()
     }
 
+    /// Returns the text covered by `range`.
+    pub fn get_range(&self, uri: &Url, range: &Range) -> Option<String> {
+        let documents = self.documents.0.read().expect("poison");
+        let text = &documents.get(uri)?.text;
+        let start = self.position_encoding.position_to_char(text, range.start);
+        let end = self
+            .position_encoding
+            .position_to_char(text, range.end)
+            .max(start);
+        Some(text.slice(start..end).to_string())
+    }
+
+    /// Returns every open document except `uri`, most relevant first: those
+    /// whose module name `uri` mentions, e.g. in an import, then those in the
+    /// same language, each group most recently edited first.
+    pub fn get_other_documents(&self, uri: &Url) -> Vec<OtherDocument> {
+        // ropes are cheap to clone, so the lock is only held for the snapshots
+        let (language_id, text, others) = {
+            let documents = self.documents.0.read().expect("poison");
+            let Some(current) = documents.get(uri) else {
+                return vec![];
+            };
+            let others = documents
+                .iter()
+                .filter(|(other_uri, _)| *other_uri != uri)
+                .map(|(other_uri, doc)| {
+                    (
+                        other_uri.clone(),
+                        doc.last_modified,
+                        doc.language_id.clone(),
+                        doc.text.clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            (
+                current.language_id.clone(),
+                current.text.to_string(),
+                others,
+            )
+        };
+        let mut others = others
+            .into_iter()
+            .map(
+                |(other_uri, last_modified, other_language_id, other_text)| {
+                    let imported =
+                        module_name(&other_uri).is_some_and(|name| mentions_word(&text, name));
+                    let relevance = (imported, other_language_id == language_id, last_modified);
+                    (relevance, other_uri, other_language_id, other_text)
+                },
+            )
+            .collect::<Vec<_>>();
+        others.sort_by(|(a, ..), (b, ..)| b.cmp(a));
+        others
+            .into_iter()
+            .map(|(_, other_uri, language_id, text)| OtherDocument {
+                filepath: other_uri.path().to_owned(),
+                relative_path: self.relative_path(&other_uri),
+                language_id,
+                text,
+            })
+            .collect()
+    }
+
+    pub fn get_indent(&self, uri: &Url) -> IndentStyle {
+        let documents = self.documents.0.read().expect("poison");
+        match documents.get(uri) {
+            Some(doc) => doc.indent,
+            None => IndentStyle::resolve(Default::default(), &Rope::new(), ""),
+        }
+    }
+
     pub fn get_language_id(&self, uri: &Url) -> String {
-        self.language_ids
+        self.documents
+            .0
             .read()
             .expect("poison")
             .get(uri)
-            .map(|s| s.to_owned())
+            .map(|doc| doc.language_id.to_owned())
             .unwrap_or_default()
     }
 
-    pub fn upsert_content(&mut self, uri: &Url, content: String) {
-        let mut docs = self.documents.write().expect("poison");
-        docs.insert(uri.clone(), content);
+    pub fn open_document(&mut self, uri: &Url, content: &str, language_id: String, version: i32) {
+        info!("opening file: {}", uri);
+        let editorconfig = uri
+            .to_file_path()
+            .map(|path| EditorConfigIndent::load(&path))
+            .unwrap_or_default();
+        let text = Rope::from_str(content);
+        let indent = IndentStyle::resolve(editorconfig, &text, &language_id);
+        self.documents.0.write().expect("poison").insert(
+            uri.clone(),
+            Document {
+                text,
+                language_id,
+                version,
+                last_modified: Instant::now(),
+                editorconfig,
+                indent,
+            },
+        );
+    }
+
+    /// Applies an incremental `didChange` event, or replaces the whole
+    /// document when the event carries no range.
+    pub fn apply_change(
+        &mut self,
+        uri: &Url,
+        change: TextDocumentContentChangeEvent,
+        version: i32,
+    ) {
+        let mut documents = self.documents.0.write().expect("poison");
+        let Some(doc) = documents.get_mut(uri) else {
+            info!("change for unopened file: {}", uri);
+            return;
+        };
+        let lines_changed = match change.range {
+            Some(range) => {
+                let start = self
+                    .position_encoding
+                    .position_to_char(&doc.text, range.start);
+                let end = self
+                    .position_encoding
+                    .position_to_char(&doc.text, range.end)
+                    .max(start);
+                doc.text.remove(start..end);
+                doc.text.insert(start, &change.text);
+                range.start.line != range.end.line || change.text.contains('\n')
+            }
+            None => {
+                doc.text = Rope::from_str(&change.text);
+                true
+            }
+        };
+        if lines_changed {
+            doc.indent = IndentStyle::resolve(doc.editorconfig, &doc.text, &doc.language_id);
+        }
+        doc.version = version;
+        doc.last_modified = Instant::now();
+    }
+
+    pub fn close_document(&mut self, uri: &Url) {
+        info!("closing file: {}", uri);
+        self.documents.0.write().expect("poison").remove(uri);
+    }
+
+    pub fn set_workspace_folders(&mut self, folders: Vec<WorkspaceFolder>) {
+        info!(
+            "workspace folders: {:?}",
+            folders.iter().map(|f| f.uri.as_str()).collect::<Vec<_>>()
+        );
+        self.workspace_folders = Arc::new(folders);
+    }
+
+    pub fn update_workspace_folders(&mut self, event: WorkspaceFoldersChangeEvent) {
+        let folders = Arc::make_mut(&mut self.workspace_folders);
+        folders.retain(|folder| {
+            !event
+                .removed
+                .iter()
+                .any(|removed| removed.uri == folder.uri)
+        });
+        folders.extend(event.added);
     }
 
-    pub fn upsert_file(&mut self, uri: &Url, content: String, language_id: Option<String>) {
-        info!("upserting file: {}", uri);
-        if let Some(language_id) = language_id {
-            self.language_ids
-                .write()
-                .expect("poison")
-                .insert(uri.clone(), language_id);
+    /// Returns the path of `uri` relative to the innermost workspace folder
+    /// containing it, or its full path outside of any workspace.
+    pub fn relative_path(&self, uri: &Url) -> String {
+        let Ok(path) = uri.to_file_path() else {
+            return uri.path().to_owned();
         };
-        self.upsert_content(uri, content);
+        self.workspace_folders
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .filter_map(|root| path.strip_prefix(root).ok().map(Path::to_path_buf))
+            .min_by_key(|relative| relative.components().count())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
     }
 
     pub fn update_client_info(&mut self, name: String, version: String) {
         self.client_info = ClientInfo { name, version };
     }
 }
+
+/// The name other files import the file at `uri` by, e.g. `foo` for both
+/// `foo.rs` and `foo/mod.rs`.
+fn module_name(uri: &Url) -> Option<&str> {
+    let mut segments = uri.path_segments()?.rev();
+    let name = match segments.next()?.split('.').next()? {
+        // named after their directory
+        "mod" | "index" | "__init__" => segments.next()?,
+        // entry points, which nothing imports
+        "main" | "lib" => return None,
+        stem => stem,
+    };
+    (!name.is_empty()).then_some(name)
+}
+
+/// Whether `word` occurs in `text` as a whole identifier.
+fn mentions_word(text: &str, word: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(word).any(|(idx, _)| {
+        !text[..idx].chars().next_back().is_some_and(is_ident)
+            && !text[idx + word.len()..]
+                .chars()
+                .next()
+                .is_some_and(is_ident)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_lsp::lsp_types::Position;
+
+    fn open(text: &str) -> (LanguageState, Url) {
+        let mut state = LanguageState::new();
+        let uri = Url::parse("file:///llm-lsp-test/main.rs").unwrap();
+        state.open_document(&uri, text, "rust".to_owned(), 1);
+        (state, uri)
+    }
+
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position::new(start.0, start.1),
+                end: Position::new(end.0, end.1),
+            }),
+            range_length: None,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn change_across_surrogate_pair() {
+        // the emoji is two UTF-16 code units
+        let (mut state, uri) = open("let s = \"😀\";\nx\n");
+        state.apply_change(&uri, change((0, 9), (0, 11), "ok"), 2);
+        assert_eq!(state.get_text(&uri), "let s = \"ok\";\nx\n");
+
+        let (mut state, uri) = open("a😀b\n");
+        state.apply_change(&uri, change((0, 3), (0, 4), "c"), 2);
+        assert_eq!(state.get_text(&uri), "a😀c\n");
+        assert_eq!(state.documents().get_version(&uri), Some(2));
+    }
+
+    #[test]
+    fn change_across_crlf() {
+        let (mut state, uri) = open("one\r\ntwo\r\nthree\r\n");
+        // joins the first two lines
+        state.apply_change(&uri, change((0, 3), (1, 0), " "), 2);
+        assert_eq!(state.get_text(&uri), "one two\r\nthree\r\n");
+        // a character past the end of the line stays before its line break
+        state.apply_change(&uri, change((0, 100), (0, 100), ";"), 3);
+        assert_eq!(state.get_text(&uri), "one two;\r\nthree\r\n");
+        state.apply_change(&uri, change((1, 5), (2, 0), "\r\nfour\r\n"), 4);
+        assert_eq!(state.get_text(&uri), "one two;\r\nthree\r\nfour\r\n");
+    }
+
+    #[test]
+    fn changes_apply_in_order() {
+        let (mut state, uri) = open("fn main() {\n}\n");
+        // each change is relative to the text the previous one left
+        for event in [
+            change((0, 11), (0, 11), "\n    let 😀 = 1;"),
+            change((1, 8), (1, 10), "x"),
+            change((1, 12), (1, 13), "2"),
+        ] {
+            state.apply_change(&uri, event, 2);
+        }
+        assert_eq!(state.get_text(&uri), "fn main() {\n    let x = 2;\n}\n");
+
+        state.apply_change(
+            &uri,
+            TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "fn main() {}\n".to_owned(),
+            },
+            3,
+        );
+        assert_eq!(state.get_text(&uri), "fn main() {}\n");
+    }
+
+    #[test]
+    fn module_names() {
+        let name = |uri: &str| module_name(&Url::parse(uri).unwrap()).map(str::to_owned);
+        assert_eq!(
+            name("file:///project/src/client.rs"),
+            Some("client".to_owned())
+        );
+        assert_eq!(
+            name("file:///project/src/client/mod.rs"),
+            Some("client".to_owned())
+        );
+        assert_eq!(
+            name("file:///project/ui/button/index.ts"),
+            Some("button".to_owned())
+        );
+        assert_eq!(
+            name("file:///project/pkg/__init__.py"),
+            Some("pkg".to_owned())
+        );
+        assert_eq!(name("file:///project/src/main.rs"), None);
+        assert_eq!(name("file:///project/src/lib.rs"), None);
+    }
+
+    #[test]
+    fn imported_documents_come_first() {
+        let (mut state, uri) = open("mod client;\n\nfn main() {}\n");
+        for (path, language_id) in [
+            ("client.rs", "rust"),
+            ("server/mod.rs", "rust"),
+            ("notes.md", "markdown"),
+        ] {
+            let other = uri.join(path).unwrap();
+            state.open_document(&other, "", language_id.to_owned(), 1);
+        }
+        let others = state.get_other_documents(&uri);
+        let paths: Vec<_> = others.iter().map(|doc| doc.filepath.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/llm-lsp-test/client.rs",
+                "/llm-lsp-test/server/mod.rs",
+                "/llm-lsp-test/notes.md",
+            ]
+        );
+    }
+}